@@ -0,0 +1,786 @@
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use crate::{Op, Type, Value};
+
+/// A single flat instruction, operands index into the pools of the `Chunk` it belongs to
+/// and jump offsets are relative to the jump itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Push(u32),
+    Dup,
+    Drop,
+    Swap,
+    Rot,
+    ReverseRot,
+    Nip,
+    Tuck,
+    Dup2,
+    Drop2,
+    Swap2,
+    Pick(u32), // 0 is the current top of the stack
+    Roll(u32), // 0 is the current top of the stack
+    Dip,
+    Keep,
+    MakeProcedure {
+        typ: u32,
+        chunk: u32,
+    },
+    Call,
+    /// A call whose procedure returns straight away after it, so the callee takes over its frame
+    TailCall,
+    Add,
+    Subtract,
+    Multiply,
+    DivMod,
+    /// Pops a value into a new local in the slot
+    NewLocal(u32),
+    /// Pushes a reference to the local in the slot
+    GetLocal(u32),
+    /// Pushes a reference to a local the procedure captured
+    GetCapture(u32),
+    Load,
+    Store,
+    TypeOf,
+    GreaterThan,
+    LessThan,
+    GreaterThanOrEqual,
+    LessThanOrEqual,
+    Compare,
+    Equal,
+    NotEqual,
+    Not,
+    MakeReferenceType,
+    MakeListType,
+    Concat,
+    Assert,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
+    Convert(u32),
+    BytesLength,
+    BytesGet,
+    BytesPush,
+    ListLength,
+    ListGet,
+    ListPush,
+    And,
+    Or,
+    Xor,
+    Jump(i32),
+    /// Pops a boolean and jumps if it is false
+    JumpIfFalse(i32),
+    /// Pops a boolean and jumps if it is true
+    JumpIfTrue(i32),
+    /// Jumps if the value on top of the stack is not one of the values in the list, without popping it
+    JumpIfNotIn {
+        values: u32,
+        offset: i32,
+    },
+    /// Pops the start, end and step of a `for` loop and starts a new loop
+    ForRangeStart,
+    /// Pops a string or bytes and starts a new `for_each` loop over its elements
+    ForEachStart,
+    /// Puts the next element of the innermost loop in a new local in the slot,
+    /// or ends the loop and jumps when there are no elements left
+    LoopNext {
+        slot: u32,
+        offset: i32,
+    },
+    /// Leaves `try`s and loops before jumping out of them with `break` or `continue`
+    Unwind {
+        handlers: u32,
+        loops: u32,
+    },
+    /// Catches values of the type that are thrown before the matching `TryEnd` by jumping to the handler
+    TryStart {
+        catch_type: u32,
+        offset: i32,
+    },
+    TryEnd,
+    Return,
+    Throw,
+    MakeCoroutine,
+    Resume,
+    Yield,
+}
+
+// instructions are copied out of the chunk for every step so keep them small
+const _: () = assert!(std::mem::size_of::<Instruction>() <= 12);
+
+/// A flat list of instructions along with everything they refer to
+#[derive(Default)]
+pub struct Chunk {
+    pub code: Vec<Instruction>,
+    pub constants: Vec<Value>,
+    pub types: Vec<Type>,
+    pub chunks: Vec<Rc<Chunk>>,
+    pub value_lists: Vec<Vec<Value>>,
+    /// The name of the local in each slot, a slot is only ever used by one local
+    pub slot_names: Vec<String>,
+    /// How many of the first slots are filled from the environment the chunk is executed in
+    pub environment: usize,
+    /// The locals a procedure takes from where it is made
+    pub captures: Vec<Capture>,
+}
+
+/// A local captured by a procedure and where to find it in the frame that makes the procedure
+#[derive(Debug, Clone)]
+pub struct Capture {
+    pub name: String,
+    pub from: CaptureSource,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureSource {
+    Slot(u32),
+    Capture(u32),
+}
+
+/// Where `break` and `continue` go for the innermost loop being lowered
+struct LoopContext {
+    handler_depth: usize,
+    loop_depth: usize,
+    /// Whether the loop keeps its own state that `break` has to end
+    has_state: bool,
+    continue_target: usize,
+    breaks: Vec<usize>,
+}
+
+#[derive(Default)]
+struct Lowering {
+    chunk: Chunk,
+    /// The slot of every local in scope, innermost scope last
+    scopes: Vec<HashMap<String, u32>>,
+    handler_depth: usize,
+    loop_depth: usize,
+    loops: Vec<LoopContext>,
+    /// Calls made while a `try` is open, which can't be tail calls since the handler is kept in
+    /// the frame the tail call would replace
+    calls_in_try: HashSet<usize>,
+}
+
+/// Lowers a tree of ops that has already been type checked into a flat chunk,
+/// the names in `environment` are locals that are given to the chunk when it is executed
+pub fn lower(ops: &[Op], environment: &[String]) -> Chunk {
+    let mut lowering = Lowering {
+        scopes: vec![HashMap::new()],
+        ..Default::default()
+    };
+    for name in environment {
+        lowering.declare(name);
+    }
+    lowering.chunk.environment = environment.len();
+    lowering.lower_block(ops);
+    lowering.chunk
+}
+
+impl Lowering {
+    fn emit(&mut self, instruction: Instruction) -> usize {
+        self.chunk.code.push(instruction);
+        self.chunk.code.len() - 1
+    }
+
+    fn offset_to(&self, from: usize, to: usize) -> i32 {
+        i32::try_from(to as isize - from as isize).expect("Jump is too far")
+    }
+
+    fn emit_jump_to(&mut self, target: usize) {
+        let offset = self.offset_to(self.chunk.code.len(), target);
+        self.emit(Instruction::Jump(offset));
+    }
+
+    /// Points the jump at `position` to the next instruction to be emitted
+    fn patch(&mut self, position: usize) {
+        let offset = self.offset_to(position, self.chunk.code.len());
+        match &mut self.chunk.code[position] {
+            Instruction::Jump(jump_offset)
+            | Instruction::JumpIfFalse(jump_offset)
+            | Instruction::JumpIfTrue(jump_offset)
+            | Instruction::JumpIfNotIn {
+                offset: jump_offset,
+                ..
+            }
+            | Instruction::LoopNext {
+                offset: jump_offset,
+                ..
+            }
+            | Instruction::TryStart {
+                offset: jump_offset,
+                ..
+            } => *jump_offset = offset,
+            _ => unreachable!(),
+        }
+    }
+
+    fn constant(&mut self, value: Value) -> u32 {
+        self.chunk.constants.push(value);
+        (self.chunk.constants.len() - 1) as u32
+    }
+
+    /// Gives a new local its own slot in the innermost scope
+    fn declare(&mut self, name: &str) -> u32 {
+        let slot = self.chunk.slot_names.len() as u32;
+        assert!(
+            self.scopes
+                .last_mut()
+                .unwrap()
+                .insert(name.to_string(), slot)
+                .is_none(),
+            "Redeclaration of local variable '{name}'"
+        );
+        self.chunk.slot_names.push(name.to_string());
+        slot
+    }
+
+    fn resolve(&self, name: &str) -> Instruction {
+        if let Some(slot) = self.scopes.iter().rev().find_map(|scope| scope.get(name)) {
+            Instruction::GetLocal(*slot)
+        } else if let Some(index) = self
+            .chunk
+            .captures
+            .iter()
+            .position(|capture| capture.name == name)
+        {
+            Instruction::GetCapture(index as u32)
+        } else {
+            panic!("Unknown local variable '{name}'")
+        }
+    }
+
+    fn typ(&mut self, typ: &Type) -> u32 {
+        match self.chunk.types.iter().position(|other| other == typ) {
+            Some(index) => index as u32,
+            None => {
+                self.chunk.types.push(typ.clone());
+                (self.chunk.types.len() - 1) as u32
+            }
+        }
+    }
+
+    /// Emits what is needed to leave everything entered since the innermost loop started,
+    /// including the loop's own state if `include_loop` is set
+    fn emit_unwind(&mut self, include_loop: bool) {
+        let context = self.loops.last().expect("Cannot break outside of a loop");
+        let mut loops = self.loop_depth - context.loop_depth;
+        if context.has_state && !include_loop {
+            loops -= 1;
+        }
+        let handlers = self.handler_depth - context.handler_depth;
+        if handlers > 0 || loops > 0 {
+            self.emit(Instruction::Unwind {
+                handlers: handlers as u32,
+                loops: loops as u32,
+            });
+        }
+    }
+
+    fn lower_block(&mut self, ops: &[Op]) {
+        for op in ops {
+            self.lower_op(op);
+        }
+    }
+
+    fn lower_op(&mut self, op: &Op) {
+        let instruction = match op {
+            Op::Push(value) => Instruction::Push(self.constant(value.clone())),
+            Op::Dup => Instruction::Dup,
+            Op::Drop => Instruction::Drop,
+            Op::Swap => Instruction::Swap,
+            Op::Rot => Instruction::Rot,
+            Op::ReverseRot => Instruction::ReverseRot,
+            Op::Nip => Instruction::Nip,
+            Op::Tuck => Instruction::Tuck,
+            Op::Dup2 => Instruction::Dup2,
+            Op::Drop2 => Instruction::Drop2,
+            Op::Swap2 => Instruction::Swap2,
+            Op::Pick(depth) => Instruction::Pick(*depth as u32),
+            Op::Roll(depth) => Instruction::Roll(*depth as u32),
+            Op::Dip => Instruction::Dip,
+            Op::Keep => Instruction::Keep,
+            Op::MakeProcedure { typ, ops } => {
+                let typ = self.typ(typ);
+                let mut procedure = Lowering {
+                    chunk: Chunk {
+                        captures: free_variables(ops)
+                            .into_iter()
+                            .map(|name| {
+                                let from = match self.resolve(&name) {
+                                    Instruction::GetLocal(slot) => CaptureSource::Slot(slot),
+                                    Instruction::GetCapture(index) => CaptureSource::Capture(index),
+                                    _ => unreachable!(),
+                                };
+                                Capture { name, from }
+                            })
+                            .collect(),
+                        ..Default::default()
+                    },
+                    scopes: vec![HashMap::new()],
+                    ..Default::default()
+                };
+                procedure.lower_block(ops);
+                procedure.mark_tail_calls();
+                self.chunk.chunks.push(Rc::new(procedure.chunk));
+                let chunk = (self.chunk.chunks.len() - 1) as u32;
+                Instruction::MakeProcedure { typ, chunk }
+            }
+            Op::Call => {
+                if self.handler_depth > 0 {
+                    self.calls_in_try.insert(self.chunk.code.len());
+                }
+                Instruction::Call
+            }
+            Op::Add => Instruction::Add,
+            Op::Subtract => Instruction::Subtract,
+            Op::Multiply => Instruction::Multiply,
+            Op::DivMod => Instruction::DivMod,
+            Op::Load => Instruction::Load,
+            Op::Store => Instruction::Store,
+            Op::TypeOf => Instruction::TypeOf,
+            Op::GreaterThan => Instruction::GreaterThan,
+            Op::LessThan => Instruction::LessThan,
+            Op::GreaterThanOrEqual => Instruction::GreaterThanOrEqual,
+            Op::LessThanOrEqual => Instruction::LessThanOrEqual,
+            Op::Compare => Instruction::Compare,
+            Op::Equal => Instruction::Equal,
+            Op::NotEqual => Instruction::NotEqual,
+            Op::Not => Instruction::Not,
+            Op::MakeReferenceType => Instruction::MakeReferenceType,
+            Op::MakeListType => Instruction::MakeListType,
+            Op::Concat => Instruction::Concat,
+            Op::Assert => Instruction::Assert,
+            Op::BitAnd => Instruction::BitAnd,
+            Op::BitOr => Instruction::BitOr,
+            Op::BitXor => Instruction::BitXor,
+            Op::ShiftLeft => Instruction::ShiftLeft,
+            Op::ShiftRight => Instruction::ShiftRight,
+            Op::Convert(typ) => Instruction::Convert(self.typ(typ)),
+            Op::BytesLength => Instruction::BytesLength,
+            Op::BytesGet => Instruction::BytesGet,
+            Op::BytesPush => Instruction::BytesPush,
+            Op::ListLength => Instruction::ListLength,
+            Op::ListGet => Instruction::ListGet,
+            Op::ListPush => Instruction::ListPush,
+            Op::And => Instruction::And,
+            Op::Or => Instruction::Or,
+            Op::Xor => Instruction::Xor,
+            Op::Return => Instruction::Return,
+            Op::Throw => Instruction::Throw,
+            Op::MakeCoroutine => Instruction::MakeCoroutine,
+            Op::Resume => Instruction::Resume,
+            Op::Yield => Instruction::Yield,
+            _ => return self.lower_control_flow(op),
+        };
+        self.emit(instruction);
+    }
+
+    /// Lowers the ops that turn into more than one instruction
+    fn lower_control_flow(&mut self, op: &Op) {
+        match op {
+            Op::DumpCurrentTypeStackInTypeChecking => {
+                unreachable!("This instruction should never make it into a final program");
+            }
+            // moving each element to the top in turn is the same as rolling it
+            Op::Over(depths) => {
+                for depth in depths {
+                    self.emit(Instruction::Roll(*depth as u32));
+                }
+            }
+            // scopes only exist while lowering, at runtime every local has its own slot
+            Op::EnterScope => self.scopes.push(HashMap::new()),
+            Op::ExitScope => {
+                self.scopes.pop();
+            }
+            // locals are created and fetched one at a time in the same order as the names
+            Op::NewLocals(names) => {
+                for name in names {
+                    let slot = self.declare(name);
+                    self.emit(Instruction::NewLocal(slot));
+                }
+            }
+            Op::GetLocals(names) => {
+                for name in names {
+                    let instruction = self.resolve(name);
+                    self.emit(instruction);
+                }
+            }
+            Op::If { then, r#else } => {
+                let to_else = self.emit(Instruction::JumpIfFalse(0));
+                self.lower_block(then);
+                if r#else.is_empty() {
+                    self.patch(to_else);
+                } else {
+                    let to_end = self.emit(Instruction::Jump(0));
+                    self.patch(to_else);
+                    self.lower_block(r#else);
+                    self.patch(to_end);
+                }
+            }
+            Op::While { condition, body } => {
+                let start = self.chunk.code.len();
+                self.lower_block(condition);
+                let to_end = self.emit(Instruction::JumpIfFalse(0));
+                self.lower_loop_body(body, start, false);
+                self.emit_jump_to(start);
+                self.patch(to_end);
+                self.patch_breaks();
+            }
+            Op::ForRange { name, body } | Op::ForEach { name, body } => {
+                self.emit(if let Op::ForRange { .. } = op {
+                    Instruction::ForRangeStart
+                } else {
+                    Instruction::ForEachStart
+                });
+                self.loop_depth += 1;
+                self.scopes.push(HashMap::new());
+                let slot = self.declare(name);
+                let start = self.emit(Instruction::LoopNext { slot, offset: 0 });
+                self.lower_loop_body(body, start, true);
+                self.scopes.pop();
+                self.emit_jump_to(start);
+                self.loop_depth -= 1;
+                self.patch(start);
+                self.patch_breaks();
+            }
+            Op::Cond { arms, default } => {
+                let mut to_end = vec![];
+                for (guard, body) in arms {
+                    self.lower_block(guard);
+                    let to_next = self.emit(Instruction::JumpIfFalse(0));
+                    self.lower_block(body);
+                    to_end.push(self.emit(Instruction::Jump(0)));
+                    self.patch(to_next);
+                }
+                if let Some(default) = default {
+                    self.lower_block(default);
+                }
+                for position in to_end {
+                    self.patch(position);
+                }
+            }
+            Op::Switch { cases, default } => {
+                let mut to_end = vec![];
+                for (values, body) in cases {
+                    self.chunk.value_lists.push(values.clone());
+                    let values = (self.chunk.value_lists.len() - 1) as u32;
+                    let to_next = self.emit(Instruction::JumpIfNotIn { values, offset: 0 });
+                    self.emit(Instruction::Drop);
+                    self.lower_block(body);
+                    to_end.push(self.emit(Instruction::Jump(0)));
+                    self.patch(to_next);
+                }
+                self.emit(Instruction::Drop);
+                if let Some(default) = default {
+                    self.lower_block(default);
+                }
+                for position in to_end {
+                    self.patch(position);
+                }
+            }
+            // the copy of the condition is left behind when the body is skipped
+            Op::AndThen(body) | Op::OrElse(body) => {
+                self.emit(Instruction::Dup);
+                let to_end = self.emit(if let Op::AndThen(_) = op {
+                    Instruction::JumpIfFalse(0)
+                } else {
+                    Instruction::JumpIfTrue(0)
+                });
+                self.emit(Instruction::Drop);
+                self.lower_block(body);
+                self.patch(to_end);
+            }
+            Op::Break => {
+                self.emit_unwind(true);
+                let position = self.emit(Instruction::Jump(0));
+                self.loops.last_mut().unwrap().breaks.push(position);
+            }
+            Op::Continue => {
+                self.emit_unwind(false);
+                let target = self.loops.last().unwrap().continue_target;
+                self.emit_jump_to(target);
+            }
+            Op::Try {
+                body,
+                catch_type,
+                handler,
+            } => {
+                let catch_type = self.typ(catch_type);
+                let to_handler = self.emit(Instruction::TryStart {
+                    catch_type,
+                    offset: 0,
+                });
+                self.handler_depth += 1;
+                self.lower_block(body);
+                self.handler_depth -= 1;
+                self.emit(Instruction::TryEnd);
+                let to_end = self.emit(Instruction::Jump(0));
+                self.patch(to_handler);
+                self.lower_block(handler);
+                self.patch(to_end);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn lower_loop_body(&mut self, body: &[Op], continue_target: usize, has_state: bool) {
+        self.loops.push(LoopContext {
+            handler_depth: self.handler_depth,
+            loop_depth: self.loop_depth - has_state as usize,
+            has_state,
+            continue_target,
+            breaks: vec![],
+        });
+        self.lower_block(body);
+    }
+
+    /// Turns every call that nothing runs after except leaving the procedure into a tail call
+    fn mark_tail_calls(&mut self) {
+        let code = &mut self.chunk.code;
+        for position in 0..code.len() {
+            if code[position] != Instruction::Call || self.calls_in_try.contains(&position) {
+                continue;
+            }
+            let mut next = position + 1;
+            let is_tail = loop {
+                match code.get(next) {
+                    None | Some(Instruction::Return) => break true,
+                    Some(Instruction::Jump(offset)) => {
+                        next = (next as isize + *offset as isize) as usize;
+                    }
+                    // a `try` that is still open has to see what the callee throws
+                    Some(Instruction::Unwind { handlers: 0, .. }) => next += 1,
+                    _ => break false,
+                }
+            };
+            if is_tail {
+                code[position] = Instruction::TailCall;
+            }
+        }
+    }
+
+    /// Points every `break` of the innermost loop to the next instruction to be emitted
+    fn patch_breaks(&mut self) {
+        let context = self.loops.pop().unwrap();
+        for position in context.breaks {
+            self.patch(position);
+        }
+    }
+}
+
+/// The names a procedure body uses without declaring them itself, in the order they are first used,
+/// which are the only locals it has to capture
+fn free_variables(ops: &[Op]) -> Vec<String> {
+    let mut free = vec![];
+    collect_free_variables(ops, &mut vec![HashSet::new()], &mut free);
+    free
+}
+
+fn collect_free_variables(ops: &[Op], scopes: &mut Vec<HashSet<String>>, free: &mut Vec<String>) {
+    for op in ops {
+        match op {
+            Op::EnterScope => scopes.push(HashSet::new()),
+            Op::ExitScope => {
+                scopes.pop();
+            }
+            Op::NewLocals(names) => scopes.last_mut().unwrap().extend(names.iter().cloned()),
+            Op::GetLocals(names) => {
+                for name in names {
+                    if !scopes.iter().any(|scope| scope.contains(name)) && !free.contains(name) {
+                        free.push(name.clone());
+                    }
+                }
+            }
+            // a nested procedure's own free variables have to be captured on the way in
+            Op::MakeProcedure { ops, .. } => collect_free_variables(ops, scopes, free),
+            Op::If { then, r#else } => {
+                collect_free_variables(then, scopes, free);
+                collect_free_variables(r#else, scopes, free);
+            }
+            Op::While { condition, body } => {
+                collect_free_variables(condition, scopes, free);
+                collect_free_variables(body, scopes, free);
+            }
+            Op::ForRange { name, body } | Op::ForEach { name, body } => {
+                scopes.push(HashSet::from([name.clone()]));
+                collect_free_variables(body, scopes, free);
+                scopes.pop();
+            }
+            Op::Cond { arms, default } => {
+                for (guard, body) in arms {
+                    collect_free_variables(guard, scopes, free);
+                    collect_free_variables(body, scopes, free);
+                }
+                if let Some(default) = default {
+                    collect_free_variables(default, scopes, free);
+                }
+            }
+            Op::Switch { cases, default } => {
+                for (_, body) in cases {
+                    collect_free_variables(body, scopes, free);
+                }
+                if let Some(default) = default {
+                    collect_free_variables(default, scopes, free);
+                }
+            }
+            Op::AndThen(body) | Op::OrElse(body) => collect_free_variables(body, scopes, free),
+            Op::Try { body, handler, .. } => {
+                collect_free_variables(body, scopes, free);
+                collect_free_variables(handler, scopes, free);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl std::fmt::Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Instruction::Push(index) => write!(f, "push #{index}"),
+            Instruction::Dup => write!(f, "dup"),
+            Instruction::Drop => write!(f, "drop"),
+            Instruction::Swap => write!(f, "swap"),
+            Instruction::Rot => write!(f, "rot"),
+            Instruction::ReverseRot => write!(f, "-rot"),
+            Instruction::Nip => write!(f, "nip"),
+            Instruction::Tuck => write!(f, "tuck"),
+            Instruction::Dup2 => write!(f, "2dup"),
+            Instruction::Drop2 => write!(f, "2drop"),
+            Instruction::Swap2 => write!(f, "2swap"),
+            Instruction::Pick(depth) => write!(f, "pick {depth}"),
+            Instruction::Roll(depth) => write!(f, "roll {depth}"),
+            Instruction::Dip => write!(f, "dip"),
+            Instruction::Keep => write!(f, "keep"),
+            Instruction::MakeProcedure { typ, chunk } => {
+                write!(f, "make_procedure type #{typ} chunk #{chunk}")
+            }
+            Instruction::Call => write!(f, "call"),
+            Instruction::TailCall => write!(f, "tail_call"),
+            Instruction::Add => write!(f, "add"),
+            Instruction::Subtract => write!(f, "sub"),
+            Instruction::Multiply => write!(f, "mul"),
+            Instruction::DivMod => write!(f, "divmod"),
+            Instruction::NewLocal(slot) => write!(f, "new_local slot #{slot}"),
+            Instruction::GetLocal(slot) => write!(f, "get_local slot #{slot}"),
+            Instruction::GetCapture(index) => write!(f, "get_capture #{index}"),
+            Instruction::Load => write!(f, "load"),
+            Instruction::Store => write!(f, "store"),
+            Instruction::TypeOf => write!(f, "type_of"),
+            Instruction::GreaterThan => write!(f, "greater"),
+            Instruction::LessThan => write!(f, "less"),
+            Instruction::GreaterThanOrEqual => write!(f, "greater_eq"),
+            Instruction::LessThanOrEqual => write!(f, "less_eq"),
+            Instruction::Compare => write!(f, "compare"),
+            Instruction::Equal => write!(f, "equal"),
+            Instruction::NotEqual => write!(f, "not_eq"),
+            Instruction::Not => write!(f, "not"),
+            Instruction::MakeReferenceType => write!(f, "ref"),
+            Instruction::MakeListType => write!(f, "list"),
+            Instruction::Concat => write!(f, "concat"),
+            Instruction::Assert => write!(f, "assert"),
+            Instruction::BitAnd => write!(f, "band"),
+            Instruction::BitOr => write!(f, "bor"),
+            Instruction::BitXor => write!(f, "bxor"),
+            Instruction::ShiftLeft => write!(f, "shl"),
+            Instruction::ShiftRight => write!(f, "shr"),
+            Instruction::Convert(typ) => write!(f, "convert type #{typ}"),
+            Instruction::BytesLength => write!(f, "bytes_len"),
+            Instruction::BytesGet => write!(f, "bytes_get"),
+            Instruction::BytesPush => write!(f, "bytes_push"),
+            Instruction::ListLength => write!(f, "list_length"),
+            Instruction::ListGet => write!(f, "list_get"),
+            Instruction::ListPush => write!(f, "list_push"),
+            Instruction::And => write!(f, "and"),
+            Instruction::Or => write!(f, "or"),
+            Instruction::Xor => write!(f, "xor"),
+            Instruction::Jump(offset) => write!(f, "jump {offset:+}"),
+            Instruction::JumpIfFalse(offset) => write!(f, "jump_if_false {offset:+}"),
+            Instruction::JumpIfTrue(offset) => write!(f, "jump_if_true {offset:+}"),
+            Instruction::JumpIfNotIn { values, offset } => {
+                write!(f, "jump_if_not_in values #{values} {offset:+}")
+            }
+            Instruction::ForRangeStart => write!(f, "for_range_start"),
+            Instruction::ForEachStart => write!(f, "for_each_start"),
+            Instruction::LoopNext { slot, offset } => {
+                write!(f, "loop_next slot #{slot} {offset:+}")
+            }
+            Instruction::Unwind { handlers, loops } => {
+                write!(f, "unwind handlers {handlers} loops {loops}")
+            }
+            Instruction::TryStart { catch_type, offset } => {
+                write!(f, "try_start type #{catch_type} {offset:+}")
+            }
+            Instruction::TryEnd => write!(f, "try_end"),
+            Instruction::Return => write!(f, "return"),
+            Instruction::Throw => write!(f, "throw"),
+            Instruction::MakeCoroutine => write!(f, "coroutine"),
+            Instruction::Resume => write!(f, "resume"),
+            Instruction::Yield => write!(f, "yield"),
+        }
+    }
+}
+
+impl std::fmt::Display for Chunk {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (index, capture) in self.captures.iter().enumerate() {
+            write!(f, "capture #{index} ({}) from ", capture.name)?;
+            match capture.from {
+                CaptureSource::Slot(slot) => writeln!(f, "slot #{slot}")?,
+                CaptureSource::Capture(index) => writeln!(f, "capture #{index}")?,
+            }
+        }
+        for (position, instruction) in self.code.iter().enumerate() {
+            write!(f, "{position:04} {instruction}")?;
+            match *instruction {
+                Instruction::Push(index) => write!(f, " ({})", self.constants[index as usize])?,
+                Instruction::MakeProcedure { typ, .. } | Instruction::Convert(typ) => {
+                    write!(f, " ({})", self.types[typ as usize])?
+                }
+                Instruction::TryStart { catch_type, .. } => {
+                    write!(f, " ({})", self.types[catch_type as usize])?
+                }
+                Instruction::NewLocal(slot)
+                | Instruction::GetLocal(slot)
+                | Instruction::LoopNext { slot, .. } => {
+                    write!(f, " ({})", self.slot_names[slot as usize])?
+                }
+                Instruction::GetCapture(index) => {
+                    write!(f, " ({})", self.captures[index as usize].name)?
+                }
+                Instruction::JumpIfNotIn { values, .. } => {
+                    write!(f, " (")?;
+                    for (i, value) in self.value_lists[values as usize].iter().enumerate() {
+                        if i > 0 {
+                            write!(f, " ")?;
+                        }
+                        write!(f, "{value}")?;
+                    }
+                    write!(f, ")")?;
+                }
+                _ => {}
+            }
+            match *instruction {
+                Instruction::Jump(offset)
+                | Instruction::JumpIfFalse(offset)
+                | Instruction::JumpIfTrue(offset)
+                | Instruction::JumpIfNotIn { offset, .. }
+                | Instruction::LoopNext { offset, .. }
+                | Instruction::TryStart { offset, .. } => {
+                    writeln!(f, " -> {:04}", position as isize + offset as isize)?
+                }
+                _ => writeln!(f)?,
+            }
+        }
+        for (index, chunk) in self.chunks.iter().enumerate() {
+            writeln!(f)?;
+            writeln!(f, "chunk #{index}:")?;
+            for line in chunk.to_string().lines() {
+                writeln!(f, "    {line}")?;
+            }
+        }
+        Ok(())
+    }
+}
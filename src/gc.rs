@@ -0,0 +1,214 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    rc::{Rc, Weak},
+};
+
+use crate::{Coroutine, Value};
+
+/// How many objects can be made before the first automatic collection
+const INITIAL_THRESHOLD: usize = 10_000;
+
+/// Every local cell and coroutine that has been made, so cycles between them can be found
+struct Heap {
+    cells: Vec<Weak<Cell<Value>>>,
+    coroutines: Vec<Weak<RefCell<Coroutine>>>,
+    /// Collect once this many objects are tracked
+    threshold: usize,
+}
+
+thread_local! {
+    static HEAP: RefCell<Heap> = const {
+        RefCell::new(Heap {
+            cells: vec![],
+            coroutines: vec![],
+            threshold: INITIAL_THRESHOLD,
+        })
+    };
+}
+
+/// An object the collector can find cycles through
+enum Object {
+    Cell(Rc<Cell<Value>>),
+    Coroutine(Rc<RefCell<Coroutine>>),
+}
+
+impl Object {
+    fn address(&self) -> *const () {
+        match self {
+            Object::Cell(cell) => Rc::as_ptr(cell) as *const (),
+            Object::Coroutine(coroutine) => Rc::as_ptr(coroutine) as *const (),
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Object::Cell(cell) => Rc::strong_count(cell),
+            Object::Coroutine(coroutine) => Rc::strong_count(coroutine),
+        }
+    }
+
+    /// Calls `visit` with the address of every object this one holds directly
+    fn trace(&self, visit: &mut dyn FnMut(*const ())) {
+        match self {
+            Object::Cell(cell) => {
+                let value = cell.replace(Value::Null);
+                trace_value(&value, visit);
+                cell.set(value);
+            }
+            // a coroutine that is borrowed is running, so whatever it holds is reachable
+            Object::Coroutine(coroutine) => {
+                if let Ok(coroutine) = coroutine.try_borrow() {
+                    coroutine.trace(visit);
+                }
+            }
+        }
+    }
+
+    /// Breaks the cycles through this object, handing what it held to `garbage` to be dropped
+    fn clear(&self, garbage: &mut Garbage) {
+        match self {
+            Object::Cell(cell) => garbage.values.push(cell.replace(Value::Null)),
+            Object::Coroutine(coroutine) => {
+                if let Ok(mut coroutine) = coroutine.try_borrow_mut() {
+                    garbage
+                        .coroutines
+                        .push(std::mem::replace(&mut *coroutine, Coroutine::Finished));
+                }
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Garbage {
+    values: Vec<Value>,
+    coroutines: Vec<Coroutine>,
+}
+
+/// Calls `visit` with the address of every cell and coroutine `value` holds directly
+pub(crate) fn trace_value(value: &Value, visit: &mut dyn FnMut(*const ())) {
+    match value {
+        Value::Function { captures, .. } => {
+            for capture in captures {
+                visit(Rc::as_ptr(capture) as *const ());
+            }
+        }
+        Value::Reference(cell) => visit(Rc::as_ptr(cell) as *const ()),
+        Value::Coroutine(_, coroutine) => visit(Rc::as_ptr(coroutine) as *const ()),
+        Value::List(_, values) => values.iter().for_each(|value| trace_value(value, visit)),
+        _ => {}
+    }
+}
+
+/// Makes a cell for a local, collecting cycles first if enough objects have been made since the
+/// last collection
+pub fn new_cell(value: Value) -> Rc<Cell<Value>> {
+    let cell = Rc::new(Cell::new(value));
+    track(|heap| heap.cells.push(Rc::downgrade(&cell)));
+    cell
+}
+
+pub fn new_coroutine(coroutine: Coroutine) -> Rc<RefCell<Coroutine>> {
+    let coroutine = Rc::new(RefCell::new(coroutine));
+    track(|heap| heap.coroutines.push(Rc::downgrade(&coroutine)));
+    coroutine
+}
+
+fn track(add: impl FnOnce(&mut Heap)) {
+    let full = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        add(&mut heap);
+        heap.cells.len() + heap.coroutines.len() >= heap.threshold
+    });
+    if full {
+        collect_cycles();
+        HEAP.with(|heap| {
+            let mut heap = heap.borrow_mut();
+            heap.threshold = INITIAL_THRESHOLD.max(2 * (heap.cells.len() + heap.coroutines.len()));
+        });
+    }
+}
+
+/// The number of cells and coroutines that have not been freed yet
+pub fn live_objects() -> usize {
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.cells.retain(|cell| cell.strong_count() > 0);
+        heap.coroutines
+            .retain(|coroutine| coroutine.strong_count() > 0);
+        heap.cells.len() + heap.coroutines.len()
+    })
+}
+
+/// Frees the cells and coroutines that are only reachable through cycles between each other,
+/// returning how many were freed
+///
+/// Anything held from outside the tracked objects, like the stack or a running frame, counts as
+/// reachable, so this is safe to call at any time.
+pub fn collect_cycles() -> usize {
+    let objects: Vec<Object> = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.cells.retain(|cell| cell.strong_count() > 0);
+        heap.coroutines
+            .retain(|coroutine| coroutine.strong_count() > 0);
+        let cells = heap
+            .cells
+            .iter()
+            .filter_map(Weak::upgrade)
+            .map(Object::Cell);
+        let coroutines = heap
+            .coroutines
+            .iter()
+            .filter_map(Weak::upgrade)
+            .map(Object::Coroutine);
+        cells.chain(coroutines).collect()
+    });
+    let indices: HashMap<*const (), usize> = objects
+        .iter()
+        .enumerate()
+        .map(|(index, object)| (object.address(), index))
+        .collect();
+
+    // whatever references are left after taking away the ones between tracked objects come from
+    // outside, the `- 1` is the `Rc` in `objects`
+    let mut outside_references: Vec<usize> = objects
+        .iter()
+        .map(|object| object.strong_count() - 1)
+        .collect();
+    for object in &objects {
+        object.trace(&mut |address| {
+            if let Some(&index) = indices.get(&address) {
+                outside_references[index] -= 1;
+            }
+        });
+    }
+
+    let mut reachable = vec![false; objects.len()];
+    let mut pending: Vec<usize> = (0..objects.len())
+        .filter(|&index| outside_references[index] > 0)
+        .collect();
+    while let Some(index) = pending.pop() {
+        if reachable[index] {
+            continue;
+        }
+        reachable[index] = true;
+        objects[index].trace(&mut |address| {
+            if let Some(&index) = indices.get(&address) {
+                pending.push(index);
+            }
+        });
+    }
+
+    // clearing every object before dropping anything means no drop sees a half cleared cycle
+    let mut garbage = Garbage::default();
+    let mut freed = 0;
+    for (object, reachable) in objects.iter().zip(reachable) {
+        if !reachable {
+            object.clear(&mut garbage);
+            freed += 1;
+        }
+    }
+    drop(garbage);
+    freed
+}
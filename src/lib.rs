@@ -1,9 +1,18 @@
+mod bytecode;
+mod gc;
+mod native;
 mod ops;
 mod parser;
+pub mod stdlib;
 mod types;
 mod value;
+mod vm;
 
+pub use bytecode::*;
+pub use gc::*;
+pub use native::*;
 pub use ops::*;
 pub use parser::*;
 pub use types::*;
 pub use value::*;
+pub use vm::*;
@@ -0,0 +1,280 @@
+use std::{any::Any, rc::Rc};
+
+use crate::{NativeFunction, Type, Value};
+
+/// A Rust type that can be taken off the stack as an argument of a native procedure
+pub trait FromValue: Sized {
+    fn value_type() -> Type;
+    /// Nothing if the value isn't of `value_type`
+    fn from_value(value: Value) -> Option<Self>;
+}
+
+/// A Rust type that can be pushed onto the stack by a native procedure
+pub trait IntoValue {
+    fn value_type() -> Type;
+    fn into_value(self) -> Value;
+}
+
+macro_rules! impl_value_conversions {
+    ($($rust:ty => $variant:ident),* $(,)?) => {
+        $(
+            impl FromValue for $rust {
+                fn value_type() -> Type {
+                    Type::$variant
+                }
+
+                fn from_value(value: Value) -> Option<Self> {
+                    match value {
+                        Value::$variant(value) => Some(value),
+                        _ => None,
+                    }
+                }
+            }
+
+            impl IntoValue for $rust {
+                fn value_type() -> Type {
+                    Type::$variant
+                }
+
+                fn into_value(self) -> Value {
+                    Value::$variant(self)
+                }
+            }
+        )*
+    };
+}
+
+impl_value_conversions! {
+    i64 => Integer,
+    u8 => U8,
+    i32 => I32,
+    u64 => U64,
+    bool => Boolean,
+    char => Character,
+    String => String,
+    Vec<u8> => Bytes,
+}
+
+macro_rules! impl_list_conversions {
+    ($($element:ty),* $(,)?) => {
+        $(
+            impl FromValue for Vec<$element> {
+                fn value_type() -> Type {
+                    Type::List(Box::new(<$element as FromValue>::value_type()))
+                }
+
+                fn from_value(value: Value) -> Option<Self> {
+                    match value {
+                        Value::List(_, values) => values.into_iter().map(FromValue::from_value).collect(),
+                        _ => None,
+                    }
+                }
+            }
+
+            impl IntoValue for Vec<$element> {
+                fn value_type() -> Type {
+                    Type::List(Box::new(<$element as IntoValue>::value_type()))
+                }
+
+                fn into_value(self) -> Value {
+                    Value::List(
+                        <$element as IntoValue>::value_type(),
+                        self.into_iter().map(IntoValue::into_value).collect(),
+                    )
+                }
+            }
+        )*
+    };
+}
+
+// `Vec<u8>` is bytes rather than a list
+impl_list_conversions!(i64, bool, char, String);
+
+impl FromValue for Type {
+    fn value_type() -> Type {
+        Type::Type
+    }
+
+    fn from_value(value: Value) -> Option<Self> {
+        match value {
+            Value::Type(typ) => Some(typ),
+            _ => None,
+        }
+    }
+}
+
+impl IntoValue for Type {
+    fn value_type() -> Type {
+        Type::Type
+    }
+
+    fn into_value(self) -> Value {
+        Value::Type(self)
+    }
+}
+
+/// A host type that native procedures can take and return as `Rc<Self>`, programs see it as
+/// `Type::Opaque(NAME)`
+pub trait OpaqueType: Any {
+    const NAME: &'static str;
+
+    fn opaque_type() -> Type {
+        Type::Opaque(Self::NAME.to_string())
+    }
+}
+
+impl<T: OpaqueType> FromValue for Rc<T> {
+    fn value_type() -> Type {
+        T::opaque_type()
+    }
+
+    fn from_value(value: Value) -> Option<Self> {
+        match &value {
+            Value::Opaque(name, _) if name == T::NAME => value.downcast_rc(),
+            _ => None,
+        }
+    }
+}
+
+impl<T: OpaqueType> IntoValue for Rc<T> {
+    fn value_type() -> Type {
+        T::opaque_type()
+    }
+
+    fn into_value(self) -> Value {
+        Value::Opaque(T::NAME.to_string(), self)
+    }
+}
+
+/// Everything a native procedure returns, pushed in order, or a value it throws
+pub trait IntoReturnValues {
+    fn return_types() -> Vec<Type>;
+    fn push_onto(self, stack: &mut Vec<Value>) -> Result<(), Value>;
+}
+
+impl IntoReturnValues for () {
+    fn return_types() -> Vec<Type> {
+        vec![]
+    }
+
+    fn push_onto(self, _: &mut Vec<Value>) -> Result<(), Value> {
+        Ok(())
+    }
+}
+
+impl<T: IntoValue> IntoReturnValues for T {
+    fn return_types() -> Vec<Type> {
+        vec![T::value_type()]
+    }
+
+    fn push_onto(self, stack: &mut Vec<Value>) -> Result<(), Value> {
+        stack.push(self.into_value());
+        Ok(())
+    }
+}
+
+impl<A: IntoValue, B: IntoValue> IntoReturnValues for (A, B) {
+    fn return_types() -> Vec<Type> {
+        vec![A::value_type(), B::value_type()]
+    }
+
+    fn push_onto(self, stack: &mut Vec<Value>) -> Result<(), Value> {
+        stack.push(self.0.into_value());
+        stack.push(self.1.into_value());
+        Ok(())
+    }
+}
+
+impl<A: IntoValue, B: IntoValue, C: IntoValue> IntoReturnValues for (A, B, C) {
+    fn return_types() -> Vec<Type> {
+        vec![A::value_type(), B::value_type(), C::value_type()]
+    }
+
+    fn push_onto(self, stack: &mut Vec<Value>) -> Result<(), Value> {
+        stack.push(self.0.into_value());
+        stack.push(self.1.into_value());
+        stack.push(self.2.into_value());
+        Ok(())
+    }
+}
+
+/// The error is thrown, so it can be caught by a `try` in the program
+impl<T: IntoReturnValues, E: IntoValue> IntoReturnValues for Result<T, E> {
+    fn return_types() -> Vec<Type> {
+        T::return_types()
+    }
+
+    fn push_onto(self, stack: &mut Vec<Value>) -> Result<(), Value> {
+        match self {
+            Ok(values) => values.push_onto(stack),
+            Err(error) => Err(error.into_value()),
+        }
+    }
+}
+
+/// A Rust function that can be called as a procedure, its type comes from its signature
+///
+/// `Arguments` is the tuple of the argument types, it only tells the implementations for
+/// different numbers of arguments apart.
+pub trait NativeProcedure<Arguments> {
+    fn procedure_type() -> Type;
+    fn into_native_function(self) -> NativeFunction;
+
+    fn into_value(self) -> Value
+    where
+        Self: Sized,
+    {
+        Value::BuiltinFunction(Self::procedure_type(), self.into_native_function())
+    }
+}
+
+macro_rules! one {
+    ($argument:ident) => {
+        1
+    };
+}
+
+macro_rules! impl_native_procedure {
+    ($($argument:ident)*) => {
+        impl<F, R, $($argument,)*> NativeProcedure<($($argument,)*)> for F
+        where
+            F: Fn($($argument),*) -> R + 'static,
+            R: IntoReturnValues,
+            $($argument: FromValue,)*
+        {
+            fn procedure_type() -> Type {
+                Type::Procedure {
+                    arguments: vec![$($argument::value_type()),*],
+                    return_values: R::return_types(),
+                }
+            }
+
+            #[allow(non_snake_case, unused_mut)]
+            fn into_native_function(self) -> NativeFunction {
+                Rc::new(move |stack| {
+                    // the last argument is on top of the stack
+                    let mut arguments = stack.drain(stack.len() - (0 $(+ one!($argument))*)..);
+                    // the type checker only knows the name of an opaque type, so a host value
+                    // of another Rust type with the same name gets through to here
+                    $(
+                        let Some($argument) = $argument::from_value(arguments.next().unwrap()) else {
+                            return Err(Value::String(format!(
+                                "The argument of type {} holds another host value than expected",
+                                $argument::value_type()
+                            )));
+                        };
+                    )*
+                    drop(arguments);
+                    self($($argument),*).push_onto(stack)
+                })
+            }
+        }
+    };
+}
+
+impl_native_procedure!();
+impl_native_procedure!(A);
+impl_native_procedure!(A B);
+impl_native_procedure!(A B C);
+impl_native_procedure!(A B C D);
+impl_native_procedure!(A B C D E);
@@ -1,6 +1,18 @@
-use std::{cell::Cell, collections::HashMap, rc::Rc};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    rc::Rc,
+    sync::{
+        atomic::{self, AtomicBool},
+        Arc,
+    },
+    vec,
+};
 
-use crate::{Type, Value};
+use crate::{new_cell, new_coroutine, trace_value, CaptureSource, Chunk, Instruction, Type, Value};
+
+/// The guard of a `cond` arm and the body that runs when it is true
+pub type CondArm = (Rc<Vec<Op>>, Rc<Vec<Op>>);
 
 pub enum Op {
     DumpCurrentTypeStackInTypeChecking,
@@ -8,7 +20,22 @@ pub enum Op {
     Dup,
     Drop,
     Over(Vec<usize>), // 0 is the current top of the stack
-    MakeProcedure { typ: Type, ops: Rc<Vec<Op>> },
+    Swap,
+    Rot,
+    ReverseRot,
+    Nip,
+    Tuck,
+    Dup2,
+    Drop2,
+    Swap2,
+    Pick(usize), // 0 is the current top of the stack
+    Roll(usize), // 0 is the current top of the stack
+    Dip,
+    Keep,
+    MakeProcedure {
+        typ: Type,
+        ops: Rc<Vec<Op>>,
+    },
     Call,
     Add,
     Subtract,
@@ -23,254 +50,1299 @@ pub enum Op {
     TypeOf,
     GreaterThan,
     LessThan,
+    GreaterThanOrEqual,
+    LessThanOrEqual,
+    Compare,
     Equal,
+    NotEqual,
     Not,
     MakeReferenceType,
-    If { then: Vec<Op>, r#else: Vec<Op> },
-    While { condition: Vec<Op>, body: Vec<Op> },
+    MakeListType,
+    If {
+        then: Rc<Vec<Op>>,
+        r#else: Rc<Vec<Op>>,
+    },
+    While {
+        condition: Rc<Vec<Op>>,
+        body: Rc<Vec<Op>>,
+    },
+    ForRange {
+        name: String,
+        body: Rc<Vec<Op>>,
+    },
+    Cond {
+        arms: Vec<CondArm>,
+        default: Option<Rc<Vec<Op>>>,
+    },
+    Switch {
+        cases: Vec<(Vec<Value>, Rc<Vec<Op>>)>,
+        default: Option<Rc<Vec<Op>>>,
+    },
+    ForEach {
+        name: String,
+        body: Rc<Vec<Op>>,
+    },
     Concat,
     Assert,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
+    Convert(Type),
+    BytesLength,
+    BytesGet,
+    BytesPush,
+    ListLength,
+    ListGet,
+    ListPush,
+    And,
+    Or,
+    Xor,
+    AndThen(Rc<Vec<Op>>),
+    OrElse(Rc<Vec<Op>>),
+    Break,
+    Continue,
+    Return,
+    Throw,
+    Try {
+        body: Rc<Vec<Op>>,
+        catch_type: Type,
+        handler: Rc<Vec<Op>>,
+    },
+    MakeCoroutine,
+    Resume,
+    Yield, // throws a string unless run by the procedure a coroutine was made from
+}
+
+#[derive(Debug)]
+pub enum RuntimeError {
+    /// A value was thrown and no `catch` for its type was found
+    Thrown(Value),
+    /// More procedures were called without returning than `Limits::max_call_depth` allows
+    CallStackExhausted,
+    /// More instructions ran than the fuel in `Limits::fuel` allowed
+    OutOfFuel,
+    /// The stack held more values than `Limits::max_stack_depth` allows
+    ValueStackExhausted,
+    /// A string, bytes or list grew longer than `Limits::max_value_length` allows
+    ValueTooLong,
+    /// The program was stopped through its `Interrupt`
+    Interrupted,
+    /// A host tried to call a value that isn't a procedure
+    NotAProcedure(Type),
+    /// A host called a procedure with arguments that don't match its type
+    WrongArguments {
+        expected: Vec<Type>,
+        found: Vec<Type>,
+    },
+}
+
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RuntimeError::Thrown(value) => write!(f, "Uncaught exception: {value}"),
+            RuntimeError::CallStackExhausted => write!(f, "Call stack exhausted"),
+            RuntimeError::OutOfFuel => write!(f, "Out of fuel"),
+            RuntimeError::ValueStackExhausted => write!(f, "Value stack exhausted"),
+            RuntimeError::ValueTooLong => write!(f, "Value too long"),
+            RuntimeError::Interrupted => write!(f, "Interrupted"),
+            RuntimeError::NotAProcedure(typ) => write!(f, "Cannot call a value of type {typ}"),
+            RuntimeError::WrongArguments { expected, found } => {
+                let join = |types: &[Type]| {
+                    types
+                        .iter()
+                        .map(Type::to_string)
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                };
+                write!(
+                    f,
+                    "Expected arguments of types ({}) but got ({})",
+                    join(expected),
+                    join(found)
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+/// Bounds on the resources a program may use while it executes
+#[derive(Debug, Clone)]
+pub struct Limits {
+    /// How deep procedure calls can nest, counting the program itself as the first frame
+    pub max_call_depth: usize,
+    /// How many more instructions can run, counted down as they do so what is left can be
+    /// checked afterwards
+    pub fuel: u64,
+    /// How many values the stack can hold at once, a coroutine's stack is counted on its own
+    pub max_stack_depth: usize,
+    /// How many bytes a string or bytes can grow to, or how many values a list can hold
+    pub max_value_length: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_call_depth: 100_000,
+            fuel: u64::MAX,
+            max_stack_depth: usize::MAX,
+            max_value_length: usize::MAX,
+        }
+    }
+}
+
+/// Stops a running program before its next instruction, it can be cloned and used from another
+/// thread
+#[derive(Debug, Clone, Default)]
+pub struct Interrupt(Arc<AtomicBool>);
+
+impl Interrupt {
+    pub fn interrupt(&self) {
+        self.0.store(true, atomic::Ordering::Relaxed);
+    }
+
+    pub fn is_interrupted(&self) -> bool {
+        self.0.load(atomic::Ordering::Relaxed)
+    }
+
+    /// Lets programs run again after an interrupt
+    pub fn reset(&self) {
+        self.0.store(false, atomic::Ordering::Relaxed);
+    }
+}
+
+fn error(message: &str) -> RuntimeError {
+    RuntimeError::Thrown(Value::String(message.into()))
+}
+
+/// The state of a coroutine between calls to `resume`
+pub enum Coroutine {
+    /// Not resumed yet, the procedure runs from the start on the first `resume`
+    Created(Value),
+    /// Stopped at a `yield`, waiting for the values it yields to be replaced with resumed ones
+    Suspended {
+        frames: Vec<Frame>,
+        stack: Vec<Value>,
+    },
+    /// Stopped partway through a `resume` because the program it runs in was paused, the same
+    /// `resume` picks up where it left off once the program continues
+    Paused {
+        frames: Vec<Frame>,
+        stack: Vec<Value>,
+    },
+    Running,
+    Finished,
+}
+
+impl Coroutine {
+    /// Calls `visit` with the address of every cell and coroutine the suspended procedure holds
+    pub(crate) fn trace(&self, visit: &mut dyn FnMut(*const ())) {
+        match self {
+            Coroutine::Created(procedure) => trace_value(procedure, visit),
+            Coroutine::Suspended { frames, stack } | Coroutine::Paused { frames, stack } => {
+                for frame in frames {
+                    for cell in frame.slots.iter().flatten().chain(&frame.captures) {
+                        visit(Rc::as_ptr(cell) as *const ());
+                    }
+                    for loop_state in &frame.loops {
+                        if let LoopState::Each(elements) = loop_state {
+                            elements
+                                .as_slice()
+                                .iter()
+                                .for_each(|value| trace_value(value, visit));
+                        }
+                    }
+                    frame
+                        .then_push
+                        .iter()
+                        .for_each(|value| trace_value(value, visit));
+                }
+                stack.iter().for_each(|value| trace_value(value, visit));
+            }
+            Coroutine::Running | Coroutine::Finished => {}
+        }
+    }
+}
+
+/// A procedure call that is currently being executed
+pub struct Frame {
+    chunk: Rc<Chunk>,
+    position: usize,
+    /// Empty until the local in the slot is created
+    slots: Vec<Option<Rc<Cell<Value>>>>,
+    captures: Vec<Rc<Cell<Value>>>,
+    loops: Vec<LoopState>,
+    handlers: Vec<Handler>,
+    /// The value `dip` and `keep` put back after the call
+    then_push: Option<Value>,
+}
+
+/// What a `for` or `for_each` loop has left to go through
+enum LoopState {
+    Range {
+        index: Option<i64>,
+        end: i64,
+        step: i64,
+    },
+    Each(vec::IntoIter<Value>),
+}
+
+/// A `try` whose body is still running
+struct Handler {
+    catch_type: u32,
+    position: usize,
+    /// The stack and loops are put back the way they were at the start of the `try`
+    depth: usize,
+    loops: usize,
+}
+
+impl Frame {
+    fn new(chunk: Rc<Chunk>, captures: Vec<Rc<Cell<Value>>>, then_push: Option<Value>) -> Frame {
+        Frame {
+            slots: vec![None; chunk.slot_names.len()],
+            chunk,
+            position: 0,
+            captures,
+            loops: vec![],
+            handlers: vec![],
+            then_push,
+        }
+    }
+
+    fn local(&self, slot: u32) -> Rc<Cell<Value>> {
+        self.slots[slot as usize]
+            .clone()
+            .expect("Local variable used before it was created")
+    }
+
+    /// Jumps relative to the instruction that was just run
+    fn jump(&mut self, offset: i32) {
+        self.position = (self.position as isize - 1 + offset as isize) as usize;
+    }
+}
+
+/// Why `run` stopped
+enum Status {
+    Finished,
+    Yielded,
+    Paused,
+}
+
+/// Everything `run` checks before each instruction
+struct Budget<'a> {
+    limits: &'a mut Limits,
+    /// How many more instructions can run before the program pauses
+    steps: u64,
+    interrupt: &'a Interrupt,
+}
+
+/// Whether a program ran to the end or stopped after the instructions it was given
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Progress {
+    Finished,
+    Paused,
 }
 
-pub fn execute<'a>(
-    ops: impl IntoIterator<Item = &'a Op>,
+/// A program that can be run a few instructions at a time, holding its stack and frames in
+/// between
+pub struct Execution {
+    frames: Vec<Frame>,
+    stack: Vec<Value>,
+    limits: Limits,
+    interrupt: Interrupt,
+}
+
+impl Execution {
+    pub fn new(
+        chunk: &Rc<Chunk>,
+        stack: Vec<Value>,
+        locals: HashMap<String, Rc<Cell<Value>>>,
+        limits: Limits,
+    ) -> Execution {
+        let mut frame = Frame::new(chunk.clone(), vec![], None);
+        for (slot, name) in chunk.slot_names[..chunk.environment].iter().enumerate() {
+            frame.slots[slot] = locals.get(name).cloned();
+        }
+        Execution {
+            frames: vec![frame],
+            stack,
+            limits,
+            interrupt: Interrupt::default(),
+        }
+    }
+
+    /// Stops through `interrupt` rather than a handle of its own, so one handle can stop several
+    /// programs
+    pub fn with_interrupt(mut self, interrupt: Interrupt) -> Execution {
+        self.interrupt = interrupt;
+        self
+    }
+
+    /// A handle that stops the program with `RuntimeError::Interrupted`
+    pub fn interrupt_handle(&self) -> Interrupt {
+        self.interrupt.clone()
+    }
+
+    /// Runs at most `steps` more instructions, the program can't be continued after an error
+    pub fn run_steps(&mut self, steps: u64) -> Result<Progress, RuntimeError> {
+        let mut budget = Budget {
+            limits: &mut self.limits,
+            steps,
+            interrupt: &self.interrupt,
+        };
+        match run(&mut self.frames, &mut self.stack, false, &mut budget) {
+            Ok(Status::Paused) => Ok(Progress::Paused),
+            Ok(_) => Ok(Progress::Finished),
+            Err(error) => {
+                self.frames.clear();
+                Err(error)
+            }
+        }
+    }
+
+    pub fn run(&mut self) -> Result<(), RuntimeError> {
+        while self.run_steps(u64::MAX)? == Progress::Paused {}
+        Ok(())
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn stack(&self) -> &[Value] {
+        &self.stack
+    }
+
+    pub fn into_stack(self) -> Vec<Value> {
+        self.stack
+    }
+
+    /// The limits the program runs under, with the fuel it has left
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+}
+
+pub fn execute(
+    chunk: &Rc<Chunk>,
     stack: &mut Vec<Value>,
     locals: HashMap<String, Rc<Cell<Value>>>,
-) {
-    let mut locals = vec![locals];
-    for op in ops {
-        match op {
-            Op::DumpCurrentTypeStackInTypeChecking => {
-                unreachable!("This instruction should never make it into a final program");
-            }
-            Op::Push(value) => {
-                stack.push(value.clone());
+) -> Result<(), RuntimeError> {
+    execute_with_limits(chunk, stack, locals, &mut Limits::default())
+}
+
+pub fn execute_with_limits(
+    chunk: &Rc<Chunk>,
+    stack: &mut Vec<Value>,
+    locals: HashMap<String, Rc<Cell<Value>>>,
+    limits: &mut Limits,
+) -> Result<(), RuntimeError> {
+    let mut execution = Execution::new(chunk, std::mem::take(stack), locals, limits.clone());
+    let result = execution.run();
+    *limits = execution.limits;
+    *stack = execution.stack;
+    result
+}
+
+/// Calls a procedure with the arguments from Rust, returning everything it leaves on the stack
+pub fn call_procedure(
+    procedure: &Value,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, RuntimeError> {
+    call_procedure_with_limits(
+        procedure,
+        arguments,
+        &mut Limits::default(),
+        &Interrupt::default(),
+    )
+}
+
+pub fn call_procedure_with_limits(
+    procedure: &Value,
+    arguments: Vec<Value>,
+    limits: &mut Limits,
+    interrupt: &Interrupt,
+) -> Result<Vec<Value>, RuntimeError> {
+    let Type::Procedure {
+        arguments: expected,
+        ..
+    } = procedure.get_type()
+    else {
+        return Err(RuntimeError::NotAProcedure(procedure.get_type()));
+    };
+    let found = arguments.iter().map(Value::get_type).collect::<Vec<_>>();
+    if found != expected {
+        return Err(RuntimeError::WrongArguments { expected, found });
+    }
+    let mut stack = arguments;
+    let mut frames = vec![];
+    call(procedure.clone(), &mut frames, &mut stack, None, limits)?;
+    let mut budget = Budget {
+        limits,
+        steps: u64::MAX,
+        interrupt,
+    };
+    run(&mut frames, &mut stack, false, &mut budget)?;
+    Ok(stack)
+}
+
+/// Runs until every frame has returned, the coroutine the frames belong to yields or the budget
+/// runs out of steps
+fn run(
+    frames: &mut Vec<Frame>,
+    stack: &mut Vec<Value>,
+    in_coroutine: bool,
+    budget: &mut Budget,
+) -> Result<Status, RuntimeError> {
+    while let Some(frame) = frames.last_mut() {
+        let Some(&instruction) = frame.chunk.code.get(frame.position) else {
+            let frame = frames.pop().unwrap();
+            stack.extend(frame.then_push);
+            continue;
+        };
+        if budget.interrupt.is_interrupted() {
+            return Err(RuntimeError::Interrupted);
+        }
+        if budget.steps == 0 {
+            return Ok(Status::Paused);
+        }
+        budget.steps -= 1;
+        if budget.limits.fuel == 0 {
+            return Err(RuntimeError::OutOfFuel);
+        }
+        budget.limits.fuel -= 1;
+        frame.position += 1;
+        let result = if let Instruction::Yield = instruction {
+            // a nested procedure has no way to suspend the procedures that called it
+            if in_coroutine && frames.len() == 1 {
+                return Ok(Status::Yielded);
             }
-            Op::Dup => {
-                let a = stack.pop().unwrap();
-                stack.push(a.clone());
-                stack.push(a);
+            Err(error(
+                "Can only yield from the procedure a coroutine was made from",
+            ))
+        } else {
+            step(instruction, frames, stack, budget)
+        };
+        match result {
+            Ok(()) => {}
+            Err(RuntimeError::Thrown(value)) => catch(frames, stack, value)?,
+            Err(error) => return Err(error),
+        }
+        if stack.len() > budget.limits.max_stack_depth {
+            return Err(RuntimeError::ValueStackExhausted);
+        }
+    }
+    Ok(Status::Finished)
+}
+
+/// Unwinds to the innermost `try` that catches the type of `value` and jumps to its handler
+fn catch(
+    frames: &mut Vec<Frame>,
+    stack: &mut Vec<Value>,
+    value: Value,
+) -> Result<(), RuntimeError> {
+    let typ = value.get_type();
+    while let Some(frame) = frames.last_mut() {
+        let index = frame
+            .handlers
+            .iter()
+            .rposition(|handler| frame.chunk.types[handler.catch_type as usize] == typ);
+        if let Some(index) = index {
+            frame.handlers.truncate(index + 1);
+            let handler = frame.handlers.pop().unwrap();
+            frame.loops.truncate(handler.loops);
+            frame.position = handler.position;
+            stack.truncate(handler.depth);
+            stack.push(value);
+            return Ok(());
+        }
+        frames.pop();
+    }
+    Err(RuntimeError::Thrown(value))
+}
+
+/// Runs a single instruction that is not a `yield`
+fn step(
+    instruction: Instruction,
+    frames: &mut Vec<Frame>,
+    stack: &mut Vec<Value>,
+    budget: &mut Budget,
+) -> Result<(), RuntimeError> {
+    match instruction {
+        Instruction::Push(index) => {
+            let value = frames.last().unwrap().chunk.constants[index as usize].clone();
+            stack.push(value);
+        }
+        Instruction::Dup => {
+            let a = stack.pop().unwrap();
+            stack.push(a.clone());
+            stack.push(a);
+        }
+        Instruction::Drop => {
+            stack.pop();
+        }
+        Instruction::Swap => {
+            let len = stack.len();
+            stack.swap(len - 1, len - 2);
+        }
+        Instruction::Rot => {
+            let len = stack.len();
+            stack[len - 3..].rotate_left(1);
+        }
+        Instruction::ReverseRot => {
+            let len = stack.len();
+            stack[len - 3..].rotate_right(1);
+        }
+        Instruction::Nip => {
+            stack.remove(stack.len() - 2);
+        }
+        Instruction::Tuck => {
+            let b = stack.last().unwrap().clone();
+            stack.insert(stack.len() - 2, b);
+        }
+        Instruction::Dup2 => {
+            stack.extend_from_within(stack.len() - 2..);
+        }
+        Instruction::Drop2 => {
+            stack.truncate(stack.len() - 2);
+        }
+        Instruction::Swap2 => {
+            let len = stack.len();
+            stack[len - 4..].rotate_left(2);
+        }
+        Instruction::Dip => {
+            let procedure = stack.pop().unwrap();
+            let value = stack.pop().unwrap();
+            call(procedure, frames, stack, Some(value), budget.limits)?;
+        }
+        Instruction::Keep => {
+            let procedure = stack.pop().unwrap();
+            let value = stack.last().unwrap().clone();
+            call(procedure, frames, stack, Some(value), budget.limits)?;
+        }
+        // the procedure a coroutine was made from keeps its frame so only it can yield
+        Instruction::TailCall if frames.len() > 1 => match stack.pop().unwrap() {
+            Value::Function {
+                chunk, captures, ..
+            } => {
+                let frame = frames.pop().unwrap();
+                frames.push(Frame::new(chunk, captures, frame.then_push));
             }
-            Op::Drop => {
-                stack.pop();
+            procedure => call(procedure, frames, stack, None, budget.limits)?,
+        },
+        Instruction::Call | Instruction::TailCall => {
+            let procedure = stack.pop().unwrap();
+            call(procedure, frames, stack, None, budget.limits)?;
+        }
+        Instruction::Add => {
+            let b = stack.pop().unwrap();
+            let a = stack.pop().unwrap();
+            match (a, b) {
+                (Value::Integer(a), Value::Integer(b)) => {
+                    stack.push(Value::Integer(a.wrapping_add(b)))
+                }
+                (Value::U8(a), Value::U8(b)) => stack.push(Value::U8(a.wrapping_add(b))),
+                (Value::I32(a), Value::I32(b)) => stack.push(Value::I32(a.wrapping_add(b))),
+                (Value::U64(a), Value::U64(b)) => stack.push(Value::U64(a.wrapping_add(b))),
+                (_, _) => unreachable!("Add expects two numbers of the same type"),
             }
-            Op::Over(depths) => {
-                for depth in depths {
-                    let value = stack.remove(stack.len() - depth - 1);
-                    stack.push(value);
+        }
+        Instruction::Subtract => {
+            let b = stack.pop().unwrap();
+            let a = stack.pop().unwrap();
+            match (a, b) {
+                (Value::Integer(a), Value::Integer(b)) => {
+                    stack.push(Value::Integer(a.wrapping_sub(b)))
                 }
+                (Value::U8(a), Value::U8(b)) => stack.push(Value::U8(a.wrapping_sub(b))),
+                (Value::I32(a), Value::I32(b)) => stack.push(Value::I32(a.wrapping_sub(b))),
+                (Value::U64(a), Value::U64(b)) => stack.push(Value::U64(a.wrapping_sub(b))),
+                (_, _) => unreachable!("Subtract expects two numbers of the same type"),
             }
-            Op::MakeProcedure { typ, ops } => {
-                let mut current_locals = HashMap::new();
-                for (name, local) in locals.iter().rev().flatten() {
-                    if !current_locals.contains_key(name) {
-                        current_locals.insert(name.clone(), local.clone());
-                    }
+        }
+        Instruction::Multiply => {
+            let b = stack.pop().unwrap();
+            let a = stack.pop().unwrap();
+            match (a, b) {
+                (Value::Integer(a), Value::Integer(b)) => {
+                    stack.push(Value::Integer(a.wrapping_mul(b)))
                 }
-                stack.push(Value::Function {
-                    typ: typ.clone(),
-                    ops: ops.clone(),
-                    locals: current_locals,
-                });
+                (Value::U8(a), Value::U8(b)) => stack.push(Value::U8(a.wrapping_mul(b))),
+                (Value::I32(a), Value::I32(b)) => stack.push(Value::I32(a.wrapping_mul(b))),
+                (Value::U64(a), Value::U64(b)) => stack.push(Value::U64(a.wrapping_mul(b))),
+                (_, _) => unreachable!("Multiply expects two numbers of the same type"),
             }
-            Op::Call => match stack.pop().unwrap() {
-                Value::Function { ops, locals, .. } => {
-                    execute(ops.iter(), stack, locals);
+        }
+        Instruction::DivMod => {
+            let b = stack.pop().unwrap();
+            let a = stack.pop().unwrap();
+            if matches!(
+                b,
+                Value::Integer(0) | Value::U8(0) | Value::I32(0) | Value::U64(0)
+            ) {
+                return Err(error("Division by zero"));
+            }
+            match (a, b) {
+                (Value::Integer(a), Value::Integer(b)) => {
+                    stack.push(Value::Integer(a.wrapping_div(b)));
+                    stack.push(Value::Integer(a.wrapping_rem(b)));
                 }
-                Value::BuiltinFunction(_, function) => {
-                    function(stack);
+                (Value::U8(a), Value::U8(b)) => {
+                    stack.push(Value::U8(a / b));
+                    stack.push(Value::U8(a % b));
                 }
-                _ => todo!(),
-            },
-            Op::Add => {
-                let b = stack.pop().unwrap();
-                let a = stack.pop().unwrap();
-                match (a, b) {
-                    (Value::Integer(a), Value::Integer(b)) => stack.push(Value::Integer(a + b)),
-                    (_, _) => todo!(),
+                (Value::I32(a), Value::I32(b)) => {
+                    stack.push(Value::I32(a.wrapping_div(b)));
+                    stack.push(Value::I32(a.wrapping_rem(b)));
                 }
-            }
-            Op::Subtract => {
-                let b = stack.pop().unwrap();
-                let a = stack.pop().unwrap();
-                match (a, b) {
-                    (Value::Integer(a), Value::Integer(b)) => stack.push(Value::Integer(a - b)),
-                    (_, _) => todo!(),
+                (Value::U64(a), Value::U64(b)) => {
+                    stack.push(Value::U64(a / b));
+                    stack.push(Value::U64(a % b));
                 }
+                (_, _) => unreachable!("Divmod expects two numbers of the same type"),
             }
-            Op::Multiply => {
-                let b = stack.pop().unwrap();
-                let a = stack.pop().unwrap();
-                match (a, b) {
-                    (Value::Integer(a), Value::Integer(b)) => stack.push(Value::Integer(a * b)),
-                    (_, _) => todo!(),
+        }
+        Instruction::Load => {
+            let reference = match stack.pop().unwrap() {
+                Value::Reference(reference) => reference,
+                _ => unreachable!("Load expects a reference"),
+            };
+            let value = reference.replace(Value::Null);
+            let clone = value.clone();
+            reference.set(value);
+            stack.push(clone);
+        }
+        Instruction::Store => {
+            let reference = match stack.pop().unwrap() {
+                Value::Reference(pointer) => pointer,
+                _ => unreachable!("Store expects a reference"),
+            };
+            let value = stack.pop().unwrap();
+            reference.set(value);
+        }
+        Instruction::TypeOf => {
+            let value = stack.pop().unwrap();
+            stack.push(Value::Type(value.get_type()));
+        }
+        Instruction::GreaterThan => {
+            let b = stack.pop().unwrap();
+            let a = stack.pop().unwrap();
+            match a.partial_cmp(&b) {
+                Some(ordering) => stack.push(Value::Boolean(ordering.is_gt())),
+                None => {
+                    unreachable!("Values compared by the type checker should always be ordered")
                 }
             }
-            Op::DivMod => {
-                let b = stack.pop().unwrap();
-                let a = stack.pop().unwrap();
-                match (a, b) {
-                    (Value::Integer(a), Value::Integer(b)) => {
-                        stack.push(Value::Integer(a / b));
-                        stack.push(Value::Integer(a % b));
-                    }
-                    (_, _) => todo!(),
+        }
+        Instruction::LessThan => {
+            let b = stack.pop().unwrap();
+            let a = stack.pop().unwrap();
+            match a.partial_cmp(&b) {
+                Some(ordering) => stack.push(Value::Boolean(ordering.is_lt())),
+                None => {
+                    unreachable!("Values compared by the type checker should always be ordered")
                 }
             }
-            Op::EnterScope => {
-                locals.push(HashMap::new());
+        }
+        Instruction::GreaterThanOrEqual => {
+            let b = stack.pop().unwrap();
+            let a = stack.pop().unwrap();
+            match a.partial_cmp(&b) {
+                Some(ordering) => stack.push(Value::Boolean(ordering.is_ge())),
+                None => {
+                    unreachable!("Values compared by the type checker should always be ordered")
+                }
             }
-            Op::ExitScope => {
-                locals.pop();
+        }
+        Instruction::LessThanOrEqual => {
+            let b = stack.pop().unwrap();
+            let a = stack.pop().unwrap();
+            match a.partial_cmp(&b) {
+                Some(ordering) => stack.push(Value::Boolean(ordering.is_le())),
+                None => {
+                    unreachable!("Values compared by the type checker should always be ordered")
+                }
             }
-            Op::NewLocals(names) => {
-                for name in names {
-                    let value = stack
-                        .pop()
-                        .expect("Expected value to create new local variable with but got nothing");
-                    assert!(
-                        locals
-                            .last_mut()
-                            .unwrap()
-                            .insert(name.clone(), Rc::new(Cell::new(value)))
-                            .is_none(),
-                        "Redeclaration of local variable '{name}'"
-                    );
+        }
+        Instruction::Compare => {
+            let b = stack.pop().unwrap();
+            let a = stack.pop().unwrap();
+            match a.partial_cmp(&b) {
+                Some(ordering) => stack.push(Value::Integer(ordering as i64)),
+                None => {
+                    unreachable!("Values compared by the type checker should always be ordered")
                 }
             }
-            Op::GetLocals(names) => {
-                for name in names {
-                    let local = locals
-                        .iter()
-                        .rev()
-                        .flatten()
-                        .find_map(|(local_name, value)| {
-                            if local_name == name {
-                                Some(value)
-                            } else {
-                                None
-                            }
-                        });
-                    if let Some(local) = local {
-                        stack.push(Value::Reference(local.clone()));
-                    } else {
-                        todo!()
+        }
+        Instruction::Equal => {
+            let b = stack.pop().unwrap();
+            let a = stack.pop().unwrap();
+            stack.push(Value::Boolean(a == b));
+        }
+        Instruction::NotEqual => {
+            let b = stack.pop().unwrap();
+            let a = stack.pop().unwrap();
+            stack.push(Value::Boolean(a != b));
+        }
+        Instruction::Not => {
+            let value = match stack.pop().unwrap() {
+                Value::Boolean(value) => value,
+                _ => unreachable!("Not expects a boolean"),
+            };
+            stack.push(Value::Boolean(!value));
+        }
+        Instruction::MakeReferenceType => {
+            let typ = match stack.pop().unwrap() {
+                Value::Type(typ) => typ,
+                _ => unreachable!("Making a reference type expects a type"),
+            };
+            stack.push(Value::Type(Type::Reference(Box::new(typ))));
+        }
+        Instruction::MakeListType => {
+            let typ = match stack.pop().unwrap() {
+                Value::Type(typ) => typ,
+                _ => unreachable!("Making a list type expects a type"),
+            };
+            stack.push(Value::Type(Type::List(Box::new(typ))));
+        }
+        Instruction::MakeProcedure { typ, chunk } => {
+            let frame = frames.last().unwrap();
+            let chunk = &frame.chunk.chunks[chunk as usize];
+            let captures = chunk
+                .captures
+                .iter()
+                .map(|capture| match capture.from {
+                    CaptureSource::Slot(slot) => frame.local(slot),
+                    CaptureSource::Capture(index) => frame.captures[index as usize].clone(),
+                })
+                .collect();
+            stack.push(Value::Function {
+                typ: frame.chunk.types[typ as usize].clone(),
+                chunk: chunk.clone(),
+                captures,
+            });
+        }
+        Instruction::Pick(depth) => {
+            let value = stack[stack.len() - depth as usize - 1].clone();
+            stack.push(value);
+        }
+        Instruction::Roll(depth) => {
+            let value = stack.remove(stack.len() - depth as usize - 1);
+            stack.push(value);
+        }
+        Instruction::NewLocal(slot) => {
+            let value = stack
+                .pop()
+                .expect("Expected value to create new local variable with but got nothing");
+            // a fresh cell each time so procedures made in earlier loop iterations keep their own
+            frames.last_mut().unwrap().slots[slot as usize] = Some(new_cell(value));
+        }
+        Instruction::GetLocal(slot) => {
+            stack.push(Value::Reference(frames.last().unwrap().local(slot)));
+        }
+        Instruction::GetCapture(index) => {
+            let local = frames.last().unwrap().captures[index as usize].clone();
+            stack.push(Value::Reference(local));
+        }
+        Instruction::Convert(typ) => {
+            let typ = &frames.last().unwrap().chunk.types[typ as usize];
+            let value = stack.pop().unwrap();
+            stack.push(match (value, typ) {
+                (Value::String(value), Type::Bytes) => Value::Bytes(value.into_bytes()),
+                (Value::Bytes(value), Type::String) => {
+                    Value::String(String::from_utf8(value).map_err(|utf8_error| {
+                        error(&format!(
+                            "Cannot convert bytes to a string: {}",
+                            utf8_error.utf8_error()
+                        ))
+                    })?)
+                }
+                // integer conversions truncate to the target width like an `as` cast
+                (value, typ) => {
+                    let value = match value {
+                        Value::Integer(value) => value as i128,
+                        Value::U8(value) => value as i128,
+                        Value::I32(value) => value as i128,
+                        Value::U64(value) => value as i128,
+                        _ => unreachable!("Only strings, bytes and numbers can be converted"),
+                    };
+                    match typ {
+                        Type::Integer => Value::Integer(value as i64),
+                        Type::U8 => Value::U8(value as u8),
+                        Type::I32 => Value::I32(value as i32),
+                        Type::U64 => Value::U64(value as u64),
+                        _ => unreachable!("Numbers can only be converted to other numbers"),
                     }
                 }
+            });
+        }
+        Instruction::Concat => {
+            let b = stack.pop().unwrap();
+            let a = stack.pop().unwrap();
+            let length = match (&a, &b) {
+                (Value::String(a), Value::String(b)) => a.len() + b.len(),
+                (Value::Bytes(a), Value::Bytes(b)) => a.len() + b.len(),
+                (_, _) => unreachable!("Concat expects two strings or two bytes"),
+            };
+            check_length(length, budget.limits)?;
+            match (a, b) {
+                (Value::String(a), Value::String(b)) => stack.push(Value::String(a + &b)),
+                (Value::Bytes(mut a), Value::Bytes(b)) => {
+                    a.extend(b);
+                    stack.push(Value::Bytes(a));
+                }
+                (_, _) => unreachable!("Concat expects two strings or two bytes"),
             }
-            Op::Load => {
-                let reference = match stack.pop().unwrap() {
-                    Value::Reference(reference) => reference,
-                    _ => todo!(),
-                };
-                let value = reference.replace(Value::Null);
-                let clone = value.clone();
-                reference.set(value);
-                stack.push(clone);
+        }
+        Instruction::Assert => {
+            let condition = match stack.pop().unwrap() {
+                Value::Boolean(value) => value,
+                _ => unreachable!("Assert expects a boolean"),
+            };
+            if !condition {
+                return Err(error("Assertion failed"));
             }
-            Op::Store => {
-                let reference = match stack.pop().unwrap() {
-                    Value::Reference(pointer) => pointer,
-                    _ => todo!(),
-                };
-                let value = stack.pop().unwrap();
-                reference.set(value);
+        }
+        Instruction::BitAnd => {
+            let b = stack.pop().unwrap();
+            let a = stack.pop().unwrap();
+            match (a, b) {
+                (Value::Integer(a), Value::Integer(b)) => stack.push(Value::Integer(a & b)),
+                (Value::U8(a), Value::U8(b)) => stack.push(Value::U8(a & b)),
+                (Value::I32(a), Value::I32(b)) => stack.push(Value::I32(a & b)),
+                (Value::U64(a), Value::U64(b)) => stack.push(Value::U64(a & b)),
+                (_, _) => unreachable!("Bitwise and expects two numbers of the same type"),
             }
-            Op::TypeOf => {
-                let value = stack.pop().unwrap();
-                stack.push(Value::Type(value.get_type()));
+        }
+        Instruction::BitOr => {
+            let b = stack.pop().unwrap();
+            let a = stack.pop().unwrap();
+            match (a, b) {
+                (Value::Integer(a), Value::Integer(b)) => stack.push(Value::Integer(a | b)),
+                (Value::U8(a), Value::U8(b)) => stack.push(Value::U8(a | b)),
+                (Value::I32(a), Value::I32(b)) => stack.push(Value::I32(a | b)),
+                (Value::U64(a), Value::U64(b)) => stack.push(Value::U64(a | b)),
+                (_, _) => unreachable!("Bitwise or expects two numbers of the same type"),
             }
-            Op::GreaterThan => {
-                let b = stack.pop().unwrap();
-                let a = stack.pop().unwrap();
-                stack.push(Value::Boolean(match (a, b) {
-                    (Value::Integer(a), Value::Integer(b)) => a > b,
-                    (_, _) => todo!(),
-                }));
+        }
+        Instruction::BitXor => {
+            let b = stack.pop().unwrap();
+            let a = stack.pop().unwrap();
+            match (a, b) {
+                (Value::Integer(a), Value::Integer(b)) => stack.push(Value::Integer(a ^ b)),
+                (Value::U8(a), Value::U8(b)) => stack.push(Value::U8(a ^ b)),
+                (Value::I32(a), Value::I32(b)) => stack.push(Value::I32(a ^ b)),
+                (Value::U64(a), Value::U64(b)) => stack.push(Value::U64(a ^ b)),
+                (_, _) => unreachable!("Bitwise xor expects two numbers of the same type"),
             }
-            Op::LessThan => {
-                let b = stack.pop().unwrap();
-                let a = stack.pop().unwrap();
-                stack.push(Value::Boolean(match (a, b) {
-                    (Value::Integer(a), Value::Integer(b)) => a < b,
-                    (_, _) => todo!(),
-                }));
+        }
+        Instruction::ShiftLeft => {
+            let amount = shift_amount(stack.pop().unwrap())?;
+            // shifting every bit out of the value leaves nothing behind
+            match stack.pop().unwrap() {
+                Value::Integer(a) => stack.push(Value::Integer(a.checked_shl(amount).unwrap_or(0))),
+                Value::U8(a) => stack.push(Value::U8(a.checked_shl(amount).unwrap_or(0))),
+                Value::I32(a) => stack.push(Value::I32(a.checked_shl(amount).unwrap_or(0))),
+                Value::U64(a) => stack.push(Value::U64(a.checked_shl(amount).unwrap_or(0))),
+                _ => unreachable!("Shift left expects a number"),
             }
-            Op::Equal => {
-                let b = stack.pop().unwrap();
-                let a = stack.pop().unwrap();
-                stack.push(Value::Boolean(a == b));
+        }
+        Instruction::ShiftRight => {
+            let amount = shift_amount(stack.pop().unwrap())?;
+            // signed types shift arithmetically so they fill with the sign bit
+            match stack.pop().unwrap() {
+                Value::Integer(a) => {
+                    stack.push(Value::Integer(a.checked_shr(amount).unwrap_or(if a < 0 {
+                        -1
+                    } else {
+                        0
+                    })))
+                }
+                Value::U8(a) => stack.push(Value::U8(a.checked_shr(amount).unwrap_or(0))),
+                Value::I32(a) => {
+                    stack.push(Value::I32(a.checked_shr(amount).unwrap_or(if a < 0 {
+                        -1
+                    } else {
+                        0
+                    })))
+                }
+                Value::U64(a) => stack.push(Value::U64(a.checked_shr(amount).unwrap_or(0))),
+                _ => unreachable!("Shift right expects a number"),
             }
-            Op::Not => {
-                let value = match stack.pop().unwrap() {
-                    Value::Boolean(value) => value,
-                    _ => todo!(),
-                };
-                stack.push(Value::Boolean(!value));
+        }
+        Instruction::BytesLength => {
+            let bytes = match stack.pop().unwrap() {
+                Value::Bytes(bytes) => bytes,
+                _ => unreachable!("Bytes length expects bytes"),
+            };
+            stack.push(Value::Integer(bytes.len() as i64));
+        }
+        Instruction::BytesGet => {
+            let index = match stack.pop().unwrap() {
+                Value::Integer(index) => index,
+                _ => unreachable!("Bytes get expects an integer index"),
+            };
+            let bytes = match stack.pop().unwrap() {
+                Value::Bytes(bytes) => bytes,
+                _ => unreachable!("Bytes get expects bytes"),
+            };
+            let byte = usize::try_from(index)
+                .ok()
+                .and_then(|index| bytes.get(index))
+                .ok_or_else(|| error("Index out of bounds for bytes"))?;
+            stack.push(Value::U8(*byte));
+        }
+        Instruction::BytesPush => {
+            let byte = match stack.pop().unwrap() {
+                Value::U8(byte) => byte,
+                _ => unreachable!("Bytes push expects a u8"),
+            };
+            let mut bytes = match stack.pop().unwrap() {
+                Value::Bytes(bytes) => bytes,
+                _ => unreachable!("Bytes push expects bytes"),
+            };
+            check_length(bytes.len() + 1, budget.limits)?;
+            bytes.push(byte);
+            stack.push(Value::Bytes(bytes));
+        }
+        Instruction::ListLength => {
+            let values = match stack.pop().unwrap() {
+                Value::List(_, values) => values,
+                _ => unreachable!("List length expects a list"),
+            };
+            stack.push(Value::Integer(values.len() as i64));
+        }
+        Instruction::ListGet => {
+            let index = match stack.pop().unwrap() {
+                Value::Integer(index) => index,
+                _ => unreachable!("List get expects an integer index"),
+            };
+            let mut values = match stack.pop().unwrap() {
+                Value::List(_, values) => values,
+                _ => unreachable!("List get expects a list"),
+            };
+            let index = usize::try_from(index)
+                .ok()
+                .filter(|&index| index < values.len())
+                .ok_or_else(|| error("Index out of bounds for list"))?;
+            stack.push(values.swap_remove(index));
+        }
+        Instruction::ListPush => {
+            let value = stack.pop().unwrap();
+            let (element_type, mut values) = match stack.pop().unwrap() {
+                Value::List(element_type, values) => (element_type, values),
+                _ => unreachable!("List push expects a list"),
+            };
+            check_length(values.len() + 1, budget.limits)?;
+            values.push(value);
+            stack.push(Value::List(element_type, values));
+        }
+        Instruction::And => {
+            let b = match stack.pop().unwrap() {
+                Value::Boolean(value) => value,
+                _ => unreachable!("And expects two booleans"),
+            };
+            let a = match stack.pop().unwrap() {
+                Value::Boolean(value) => value,
+                _ => unreachable!("And expects two booleans"),
+            };
+            stack.push(Value::Boolean(a && b));
+        }
+        Instruction::Or => {
+            let b = match stack.pop().unwrap() {
+                Value::Boolean(value) => value,
+                _ => unreachable!("Or expects two booleans"),
+            };
+            let a = match stack.pop().unwrap() {
+                Value::Boolean(value) => value,
+                _ => unreachable!("Or expects two booleans"),
+            };
+            stack.push(Value::Boolean(a || b));
+        }
+        Instruction::Xor => {
+            let b = match stack.pop().unwrap() {
+                Value::Boolean(value) => value,
+                _ => unreachable!("Xor expects two booleans"),
+            };
+            let a = match stack.pop().unwrap() {
+                Value::Boolean(value) => value,
+                _ => unreachable!("Xor expects two booleans"),
+            };
+            stack.push(Value::Boolean(a != b));
+        }
+        Instruction::Jump(offset) => frames.last_mut().unwrap().jump(offset),
+        Instruction::JumpIfFalse(offset) | Instruction::JumpIfTrue(offset) => {
+            let condition = match stack.pop().unwrap() {
+                Value::Boolean(value) => value,
+                _ => unreachable!("A conditional jump expects a boolean"),
+            };
+            if condition == matches!(instruction, Instruction::JumpIfTrue(_)) {
+                frames.last_mut().unwrap().jump(offset);
             }
-            Op::MakeReferenceType => {
-                let typ = match stack.pop().unwrap() {
-                    Value::Type(typ) => typ,
-                    _ => todo!(),
-                };
-                stack.push(Value::Type(Type::Reference(Box::new(typ))));
+        }
+        Instruction::JumpIfNotIn { values, offset } => {
+            let frame = frames.last_mut().unwrap();
+            if !frame.chunk.value_lists[values as usize].contains(stack.last().unwrap()) {
+                frame.jump(offset);
             }
-            Op::If { then, r#else } => {
-                let condition = stack.pop().unwrap();
-                match condition {
-                    Value::Boolean(condition) => {
-                        let mut current_locals = HashMap::new();
-                        for (name, local) in locals.iter().rev().flatten() {
-                            if !current_locals.contains_key(name) {
-                                current_locals.insert(name.clone(), local.clone());
-                            }
-                        }
-                        if condition {
-                            execute(then, stack, current_locals);
-                        } else {
-                            execute(r#else, stack, current_locals);
-                        }
-                    }
-                    _ => todo!(),
-                }
+        }
+        Instruction::ForRangeStart => {
+            let step = match stack.pop().unwrap() {
+                Value::Integer(value) => value,
+                _ => unreachable!("The step of a for loop should be an integer"),
+            };
+            let end = match stack.pop().unwrap() {
+                Value::Integer(value) => value,
+                _ => unreachable!("The end of a for loop should be an integer"),
+            };
+            let index = match stack.pop().unwrap() {
+                Value::Integer(value) => value,
+                _ => unreachable!("The start of a for loop should be an integer"),
+            };
+            if step == 0 {
+                return Err(error("The step of a for loop cannot be 0"));
             }
-            Op::While { condition, body } => loop {
-                let mut current_locals = HashMap::new();
-                for (name, local) in locals.iter().rev().flatten() {
-                    if !current_locals.contains_key(name) {
-                        current_locals.insert(name.clone(), local.clone());
+            frames.last_mut().unwrap().loops.push(LoopState::Range {
+                index: Some(index),
+                end,
+                step,
+            });
+        }
+        Instruction::ForEachStart => {
+            let elements: Vec<Value> = match stack.pop().unwrap() {
+                Value::String(value) => value.chars().map(Value::Character).collect(),
+                Value::Bytes(value) => value.into_iter().map(Value::U8).collect(),
+                Value::List(_, values) => values,
+                _ => unreachable!("Only strings, bytes and lists can be looped over"),
+            };
+            frames
+                .last_mut()
+                .unwrap()
+                .loops
+                .push(LoopState::Each(elements.into_iter()));
+        }
+        Instruction::LoopNext { slot, offset } => {
+            let frame = frames.last_mut().unwrap();
+            let element = match frame.loops.last_mut().unwrap() {
+                LoopState::Range { index, end, step } => match *index {
+                    Some(current)
+                        if (*step > 0 && current < *end) || (*step < 0 && current > *end) =>
+                    {
+                        // running past the end of the integers ends the loop
+                        *index = current.checked_add(*step);
+                        Some(Value::Integer(current))
                     }
+                    _ => None,
+                },
+                LoopState::Each(elements) => elements.next(),
+            };
+            if let Some(element) = element {
+                frame.slots[slot as usize] = Some(new_cell(element));
+            } else {
+                frame.loops.pop();
+                frame.jump(offset);
+            }
+        }
+        Instruction::Unwind { handlers, loops } => {
+            let frame = frames.last_mut().unwrap();
+            frame
+                .handlers
+                .truncate(frame.handlers.len() - handlers as usize);
+            frame.loops.truncate(frame.loops.len() - loops as usize);
+        }
+        Instruction::TryStart { catch_type, offset } => {
+            let frame = frames.last_mut().unwrap();
+            frame.handlers.push(Handler {
+                catch_type,
+                position: (frame.position as isize - 1 + offset as isize) as usize,
+                depth: stack.len(),
+                loops: frame.loops.len(),
+            });
+        }
+        Instruction::TryEnd => {
+            frames.last_mut().unwrap().handlers.pop();
+        }
+        Instruction::Return => {
+            let frame = frames.pop().unwrap();
+            stack.extend(frame.then_push);
+        }
+        Instruction::Throw => return Err(RuntimeError::Thrown(stack.pop().unwrap())),
+        Instruction::MakeCoroutine => {
+            let procedure = stack.pop().unwrap();
+            let typ = match procedure.get_type() {
+                Type::Procedure {
+                    arguments,
+                    return_values,
+                } => Type::Coroutine {
+                    resume_values: arguments,
+                    yield_values: return_values,
+                },
+                _ => unreachable!("A coroutine can only be made from a procedure"),
+            };
+            stack.push(Value::Coroutine(
+                typ,
+                new_coroutine(Coroutine::Created(procedure)),
+            ));
+        }
+        Instruction::Resume => {
+            let (typ, coroutine) = match stack.pop().unwrap() {
+                Value::Coroutine(typ, coroutine) => (typ, coroutine),
+                _ => unreachable!("Resume expects a coroutine"),
+            };
+            let (resume_count, yield_count) = match &typ {
+                Type::Coroutine {
+                    resume_values,
+                    yield_values,
+                } => (resume_values.len(), yield_values.len()),
+                _ => unreachable!(),
+            };
+            // a resume that was paused partway has already taken its arguments, and picking it
+            // back up is not counted as another instruction so a program given one step at a
+            // time still gets somewhere
+            let arguments = if let Coroutine::Paused { .. } = *coroutine.borrow() {
+                budget.steps += 1;
+                budget.limits.fuel += 1;
+                vec![]
+            } else {
+                stack.split_off(stack.len() - resume_count)
+            };
+            // the coroutine runs on its own frames but still counts towards the call depth
+            let max_call_depth = budget.limits.max_call_depth;
+            budget.limits.max_call_depth = max_call_depth.saturating_sub(frames.len());
+            let result = resume(&coroutine, arguments, yield_count, budget);
+            budget.limits.max_call_depth = max_call_depth;
+            match result? {
+                Some((values, yielded)) => {
+                    stack.extend(values);
+                    stack.push(Value::Boolean(yielded));
                 }
-                execute(condition, stack, current_locals.clone());
-                let condition = stack.pop().unwrap();
-                match condition {
-                    Value::Boolean(condition) => {
-                        if !condition {
-                            break;
-                        }
-                        execute(body, stack, current_locals);
-                    }
-                    _ => todo!(),
+                // the program pauses before the next instruction, so run this one again after
+                None => {
+                    stack.push(Value::Coroutine(typ, coroutine));
+                    frames.last_mut().unwrap().position -= 1;
                 }
-            },
-            Op::Concat => {
-                let b = match stack.pop().unwrap() {
-                    Value::String(value) => value,
-                    _ => todo!(),
-                };
-                let a = match stack.pop().unwrap() {
-                    Value::String(value) => value,
-                    _ => todo!(),
-                };
-                stack.push(Value::String(a + &b));
             }
-            Op::Assert => {
-                let condition = match stack.pop().unwrap() {
-                    Value::Boolean(value) => value,
-                    _ => todo!(),
-                };
-                assert!(condition);
+        }
+        Instruction::Yield => unreachable!("Yields are handled by `run`"),
+    }
+    Ok(())
+}
+
+/// Calls a procedure, pushing `then_push` once it returns
+fn call(
+    procedure: Value,
+    frames: &mut Vec<Frame>,
+    stack: &mut Vec<Value>,
+    then_push: Option<Value>,
+    limits: &Limits,
+) -> Result<(), RuntimeError> {
+    match procedure {
+        Value::Function {
+            chunk, captures, ..
+        } => {
+            if frames.len() >= limits.max_call_depth {
+                return Err(RuntimeError::CallStackExhausted);
+            }
+            frames.push(Frame::new(chunk, captures, then_push));
+        }
+        Value::BuiltinFunction(typ, function) => {
+            function(stack).map_err(RuntimeError::Thrown)?;
+            let Type::Procedure { return_values, .. } = typ else {
+                unreachable!()
+            };
+            // builtins can make values of any length, so what they return is checked afterwards
+            for value in &stack[stack.len() - return_values.len()..] {
+                check_value_length(value, limits)?;
             }
+            stack.extend(then_push);
+        }
+        _ => unreachable!("Only procedures can be called"),
+    }
+    Ok(())
+}
+
+/// Runs a coroutine until it yields or finishes, returning the values it produced
+/// and whether it yielded, or nothing if the program paused first
+fn resume(
+    coroutine: &RefCell<Coroutine>,
+    arguments: Vec<Value>,
+    yield_count: usize,
+    budget: &mut Budget,
+) -> Result<Option<(Vec<Value>, bool)>, RuntimeError> {
+    let (mut frames, mut stack) = match coroutine.replace(Coroutine::Running) {
+        Coroutine::Created(procedure) => {
+            let mut stack = arguments;
+            let mut frames = vec![];
+            // builtins have no frames so they run to completion here
+            if let Err(error) = call(procedure, &mut frames, &mut stack, None, budget.limits) {
+                coroutine.replace(Coroutine::Finished);
+                return Err(error);
+            }
+            (frames, stack)
+        }
+        Coroutine::Suspended { frames, mut stack } => {
+            stack.extend(arguments);
+            (frames, stack)
+        }
+        Coroutine::Paused { frames, stack } => (frames, stack),
+        Coroutine::Running => {
+            return Err(error("Cannot resume a coroutine that is already running"))
+        }
+        Coroutine::Finished => {
+            coroutine.replace(Coroutine::Finished);
+            return Err(error("Cannot resume a coroutine that has finished"));
         }
+    };
+    match run(&mut frames, &mut stack, true, budget) {
+        Ok(Status::Yielded) => {
+            let values = stack.split_off(stack.len() - yield_count);
+            coroutine.replace(Coroutine::Suspended { frames, stack });
+            Ok(Some((values, true)))
+        }
+        Ok(Status::Finished) => {
+            coroutine.replace(Coroutine::Finished);
+            Ok(Some((stack, false)))
+        }
+        Ok(Status::Paused) => {
+            coroutine.replace(Coroutine::Paused { frames, stack });
+            Ok(None)
+        }
+        Err(error) => {
+            coroutine.replace(Coroutine::Finished);
+            Err(error)
+        }
+    }
+}
+
+fn check_length(length: usize, limits: &Limits) -> Result<(), RuntimeError> {
+    if length > limits.max_value_length {
+        return Err(RuntimeError::ValueTooLong);
+    }
+    Ok(())
+}
+
+/// Checks a string, bytes or list along with the values in the list
+fn check_value_length(value: &Value, limits: &Limits) -> Result<(), RuntimeError> {
+    match value {
+        Value::String(string) => check_length(string.len(), limits),
+        Value::Bytes(bytes) => check_length(bytes.len(), limits),
+        Value::List(_, values) => {
+            check_length(values.len(), limits)?;
+            values
+                .iter()
+                .try_for_each(|value| check_value_length(value, limits))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn shift_amount(amount: Value) -> Result<u32, RuntimeError> {
+    match amount {
+        Value::Integer(amount) if amount < 0 => Err(error("Cannot shift by a negative amount")),
+        Value::Integer(amount) => Ok(u32::try_from(amount).unwrap_or(u32::MAX)),
+        _ => unreachable!("The shift amount should be an integer"),
     }
 }
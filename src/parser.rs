@@ -3,12 +3,18 @@ use std::{cell::Cell, collections::HashMap, rc::Rc};
 use lazy_static::lazy_static;
 use regex::Regex;
 
-use crate::{execute, type_check, Op, Type, Value};
+use crate::{execute, lower, new_cell, type_check, CondArm, Op, Type, Value};
 
 enum ParseScope {
     Over {
         old_ops: Vec<Op>,
     },
+    Pick {
+        old_ops: Vec<Op>,
+    },
+    Roll {
+        old_ops: Vec<Op>,
+    },
     Var {
         old_ops: Vec<Op>,
     },
@@ -16,9 +22,11 @@ enum ParseScope {
         old_ops: Vec<Op>,
     },
     ProcTypeParameterTypes {
+        coroutine: bool,
         old_ops: Vec<Op>,
     },
     ProcTypeReturnTypes {
+        coroutine: bool,
         parameter_types: Vec<Type>,
         old_ops: Vec<Op>,
     },
@@ -52,30 +60,110 @@ enum ParseScope {
     Const {
         old_ops: Vec<Op>,
     },
+    ForName {
+        each: bool,
+        old_ops: Vec<Op>,
+    },
+    ForBody {
+        each: bool,
+        name: String,
+        old_ops: Vec<Op>,
+    },
+    CondGuard {
+        arms: Vec<CondArm>,
+        old_ops: Vec<Op>,
+    },
+    CondArm {
+        guard_ops: Vec<Op>,
+        arms: Vec<CondArm>,
+        old_ops: Vec<Op>,
+    },
+    CondElse {
+        arms: Vec<CondArm>,
+        old_ops: Vec<Op>,
+    },
+    SwitchCases {
+        cases: Vec<(Vec<Value>, Rc<Vec<Op>>)>,
+        old_ops: Vec<Op>,
+    },
+    SwitchCaseValues {
+        old_ops: Vec<Op>,
+    },
+    SwitchCaseBody {
+        values: Vec<Value>,
+        old_ops: Vec<Op>,
+    },
+    SwitchElse {
+        cases: Vec<(Vec<Value>, Rc<Vec<Op>>)>,
+        old_ops: Vec<Op>,
+    },
+    TryBody {
+        old_ops: Vec<Op>,
+    },
+    CatchType {
+        body_ops: Vec<Op>,
+        old_ops: Vec<Op>,
+    },
+    CatchBody {
+        body_ops: Vec<Op>,
+        catch_type: Type,
+        old_ops: Vec<Op>,
+    },
+    AndThen {
+        old_ops: Vec<Op>,
+    },
+    OrElse {
+        old_ops: Vec<Op>,
+    },
+    EmptyList {
+        old_ops: Vec<Op>,
+    },
 }
 
 pub fn compile_ops(
-    mut source: &str,
+    source: &str,
     builtin_vars: &HashMap<String, Value>,
     constants: HashMap<String, Vec<Value>>,
 ) -> Vec<Op> {
-    let builtin_var_types = builtin_vars
+    let builtin_vars = builtin_vars
         .iter()
-        .map(|(name, value)| (name.clone(), value.get_type()))
-        .collect::<HashMap<_, _>>();
-    let builtin_var_values = builtin_vars
+        .map(|(name, value)| (name.clone(), new_cell(value.clone())))
+        .collect();
+    compile_ops_on_stack(source, &builtin_vars, constants, &[])
+}
+
+/// Compiles a program that starts with values of the given types already on the stack
+///
+/// Constants are evaluated with the cells of `builtin_vars` themselves, so procedures made by them
+/// share the variables with the program.
+pub fn compile_ops_on_stack(
+    mut source: &str,
+    builtin_vars: &HashMap<String, Rc<Cell<Value>>>,
+    constants: HashMap<String, Vec<Value>>,
+    stack: &[Type],
+) -> Vec<Op> {
+    let builtin_var_types = builtin_vars
         .iter()
-        .map(|(name, value)| (name.clone(), Rc::new(Cell::new(value.clone()))))
+        .map(|(name, cell)| {
+            let value = cell.replace(Value::Null);
+            let typ = value.get_type();
+            cell.set(value);
+            (name.clone(), typ)
+        })
         .collect::<HashMap<_, _>>();
+    let builtin_var_names = builtin_vars.keys().cloned().collect::<Vec<_>>();
 
     lazy_static! {
         static ref WHITESPACE: Regex = Regex::new(r"^\s+").unwrap();
-        static ref NUMBER: Regex = Regex::new(r"^[0-9]+").unwrap();
+        static ref SHUFFLE_WORD: Regex = Regex::new(r"^(-rot|2dup|2drop|2swap)\b").unwrap();
+        static ref NUMBER: Regex = Regex::new(r"^([0-9]+)(u8|i32|u64)?").unwrap();
         static ref IDENTIFIER: Regex = Regex::new(r"^[_A-Za-z][_0-9A-Za-z]*").unwrap();
         static ref LABEL: Regex = Regex::new(r"^:([_A-Za-z][_0-9A-Za-z]*)").unwrap();
         static ref STRING_LITERAL: Regex = Regex::new(r#"^"(.*?)""#).unwrap();
         static ref PROCEDURE_ARROW: Regex = Regex::new(r"^\s*->\s*\(").unwrap();
         static ref ELSE: Regex = Regex::new(r"^\s*else\s*\{").unwrap();
+        static ref CATCH: Regex = Regex::new(r"^\s*catch\s*\(").unwrap();
+        static ref CLOSE_BRACE: Regex = Regex::new(r"^\s*\}").unwrap();
         static ref DUMP_TYPES: Regex = Regex::new(r"^\?\?\?").unwrap();
     }
 
@@ -84,16 +172,43 @@ pub fn compile_ops(
     let mut ops = vec![Op::EnterScope];
     let mut constants = vec![constants];
 
-    while source.len() > 0 {
+    while !source.is_empty() {
         if let Some(m) = WHITESPACE.find(source) {
             source = &source[m.as_str().len()..];
         } else if let Some(m) = DUMP_TYPES.find(source) {
             source = &source[m.as_str().len()..];
             ops.push(Op::DumpCurrentTypeStackInTypeChecking);
+        } else if let Some(m) = SHUFFLE_WORD.find(source) {
+            let word = m.as_str();
+            source = &source[word.len()..];
+            ops.push(match word {
+                "-rot" => Op::ReverseRot,
+                "2dup" => Op::Dup2,
+                "2drop" => Op::Drop2,
+                "2swap" => Op::Swap2,
+                _ => unreachable!(),
+            });
         } else if let Some(m) = NUMBER.find(source) {
-            let number = m.as_str();
-            source = &source[number.len()..];
-            ops.push(Op::Push(Value::Integer(number.parse().unwrap())));
+            let str = m.as_str();
+            source = &source[str.len()..];
+            let captures = NUMBER.captures(str).unwrap();
+            let number = &captures[1];
+            let suffix = captures.get(2).map(|m| m.as_str());
+            ops.push(Op::Push(match suffix {
+                None => Value::Integer(number.parse().unwrap_or_else(|_| {
+                    panic!("Integer literal '{number}' does not fit in type 'int'")
+                })),
+                Some("u8") => Value::U8(number.parse().unwrap_or_else(|_| {
+                    panic!("Integer literal '{number}' does not fit in type 'u8'")
+                })),
+                Some("i32") => Value::I32(number.parse().unwrap_or_else(|_| {
+                    panic!("Integer literal '{number}' does not fit in type 'i32'")
+                })),
+                Some("u64") => Value::U64(number.parse().unwrap_or_else(|_| {
+                    panic!("Integer literal '{number}' does not fit in type 'u64'")
+                })),
+                Some(_) => unreachable!(),
+            }));
         } else if let Some(m) = STRING_LITERAL.find(source) {
             let str = m.as_str();
             source = &source[str.len()..];
@@ -109,8 +224,18 @@ pub fn compile_ops(
             source = &source[identifier.len()..];
             match identifier {
                 "int" => ops.push(Op::Push(Value::Type(Type::Integer))),
+                "type" => ops.push(Op::Push(Value::Type(Type::Type))),
+                "string" => ops.push(Op::Push(Value::Type(Type::String))),
+                "label" => ops.push(Op::Push(Value::Type(Type::Label))),
+                "bool" => ops.push(Op::Push(Value::Type(Type::Boolean))),
+                "char" => ops.push(Op::Push(Value::Type(Type::Character))),
+                "u8" => ops.push(Op::Push(Value::Type(Type::U8))),
+                "i32" => ops.push(Op::Push(Value::Type(Type::I32))),
+                "u64" => ops.push(Op::Push(Value::Type(Type::U64))),
+                "bytes" => ops.push(Op::Push(Value::Type(Type::Bytes))),
                 "dup" => ops.push(Op::Dup),
                 "ref" => ops.push(Op::MakeReferenceType),
+                "list" => ops.push(Op::MakeListType),
                 "drop" => ops.push(Op::Drop),
                 "over" => {
                     if let Some(m) = WHITESPACE.find(source) {
@@ -129,7 +254,35 @@ pub fn compile_ops(
                 "load" => ops.push(Op::Load),
                 "store" => ops.push(Op::Store),
                 "call" => ops.push(Op::Call),
-                "swap" => ops.push(Op::Over(vec![1])),
+                "coroutine" => ops.push(Op::MakeCoroutine),
+                "resume" => ops.push(Op::Resume),
+                "yield" => ops.push(Op::Yield),
+                "swap" => ops.push(Op::Swap),
+                "rot" => ops.push(Op::Rot),
+                "nip" => ops.push(Op::Nip),
+                "tuck" => ops.push(Op::Tuck),
+                "dip" => ops.push(Op::Dip),
+                "keep" => ops.push(Op::Keep),
+                "pick" => {
+                    if let Some(m) = WHITESPACE.find(source) {
+                        source = &source[m.as_str().len()..];
+                    }
+                    assert_eq!(source.chars().next().unwrap(), '(');
+                    source = &source[1..];
+                    parse_scopes.push(ParseScope::Pick { old_ops: ops });
+                    ops = vec![Op::EnterScope];
+                    constants.push(HashMap::new());
+                }
+                "roll" => {
+                    if let Some(m) = WHITESPACE.find(source) {
+                        source = &source[m.as_str().len()..];
+                    }
+                    assert_eq!(source.chars().next().unwrap(), '(');
+                    source = &source[1..];
+                    parse_scopes.push(ParseScope::Roll { old_ops: ops });
+                    ops = vec![Op::EnterScope];
+                    constants.push(HashMap::new());
+                }
                 "var" => {
                     if let Some(m) = WHITESPACE.find(source) {
                         source = &source[m.as_str().len()..];
@@ -150,13 +303,16 @@ pub fn compile_ops(
                     ops = vec![Op::EnterScope];
                     constants.push(HashMap::new());
                 }
-                "proc_type" => {
+                "proc_type" | "coroutine_type" => {
                     if let Some(m) = WHITESPACE.find(source) {
                         source = &source[m.as_str().len()..];
                     }
                     assert_eq!(source.chars().next().unwrap(), '(');
                     source = &source[1..];
-                    parse_scopes.push(ParseScope::ProcTypeParameterTypes { old_ops: ops });
+                    parse_scopes.push(ParseScope::ProcTypeParameterTypes {
+                        coroutine: identifier == "coroutine_type",
+                        old_ops: ops,
+                    });
                     ops = vec![Op::EnterScope];
                     constants.push(HashMap::new());
                 }
@@ -178,8 +334,83 @@ pub fn compile_ops(
                     ops = vec![Op::EnterScope];
                     constants.push(HashMap::new());
                 }
+                "for" | "for_step" | "for_each" => {
+                    if let Some(m) = WHITESPACE.find(source) {
+                        source = &source[m.as_str().len()..];
+                    }
+                    assert_eq!(source.chars().next().unwrap(), '(');
+                    source = &source[1..];
+                    if identifier == "for" {
+                        ops.push(Op::Push(Value::Integer(1)));
+                    }
+                    parse_scopes.push(ParseScope::ForName {
+                        each: identifier == "for_each",
+                        old_ops: ops,
+                    });
+                    ops = vec![Op::EnterScope];
+                    constants.push(HashMap::new());
+                }
+                "cond" => {
+                    if let Some(m) = WHITESPACE.find(source) {
+                        source = &source[m.as_str().len()..];
+                    }
+                    assert_eq!(source.chars().next().unwrap(), '{');
+                    source = &source[1..];
+                    parse_scopes.push(ParseScope::CondGuard {
+                        arms: vec![],
+                        old_ops: ops,
+                    });
+                    ops = vec![Op::EnterScope];
+                    constants.push(HashMap::new());
+                }
+                "switch" => {
+                    if let Some(m) = WHITESPACE.find(source) {
+                        source = &source[m.as_str().len()..];
+                    }
+                    assert_eq!(source.chars().next().unwrap(), '{');
+                    source = &source[1..];
+                    parse_scopes.push(ParseScope::SwitchCases {
+                        cases: vec![],
+                        old_ops: ops,
+                    });
+                    ops = vec![];
+                }
+                "case" => {
+                    assert!(
+                        matches!(parse_scopes.last(), Some(ParseScope::SwitchCases { .. })),
+                        "A case can only be used directly inside a switch"
+                    );
+                    assert!(ops.is_empty(), "Only cases can be inside a switch");
+                    if let Some(m) = WHITESPACE.find(source) {
+                        source = &source[m.as_str().len()..];
+                    }
+                    assert_eq!(source.chars().next().unwrap(), '(');
+                    source = &source[1..];
+                    parse_scopes.push(ParseScope::SwitchCaseValues { old_ops: ops });
+                    ops = vec![Op::EnterScope];
+                    constants.push(HashMap::new());
+                }
+                "throw" => ops.push(Op::Throw),
+                "try" => {
+                    if let Some(m) = WHITESPACE.find(source) {
+                        source = &source[m.as_str().len()..];
+                    }
+                    assert_eq!(source.chars().next().unwrap(), '{');
+                    source = &source[1..];
+                    parse_scopes.push(ParseScope::TryBody { old_ops: ops });
+                    ops = vec![Op::EnterScope];
+                    constants.push(HashMap::new());
+                }
+                "break" => ops.push(Op::Break),
+                "continue" => ops.push(Op::Continue),
+                "return" => ops.push(Op::Return),
                 "greater" => ops.push(Op::GreaterThan),
                 "less" => ops.push(Op::LessThan),
+                "greater_eq" => ops.push(Op::GreaterThanOrEqual),
+                "less_eq" => ops.push(Op::LessThanOrEqual),
+                "equal" => ops.push(Op::Equal),
+                "not_eq" => ops.push(Op::NotEqual),
+                "compare" => ops.push(Op::Compare),
                 "const" => {
                     if let Some(m) = WHITESPACE.find(source) {
                         source = &source[m.as_str().len()..];
@@ -191,7 +422,60 @@ pub fn compile_ops(
                     constants.push(HashMap::new());
                 }
                 "concat" => ops.push(Op::Concat),
+                "true" => ops.push(Op::Push(Value::Boolean(true))),
+                "false" => ops.push(Op::Push(Value::Boolean(false))),
+                "not" => ops.push(Op::Not),
+                "and" => ops.push(Op::And),
+                "or" => ops.push(Op::Or),
+                "xor" => ops.push(Op::Xor),
+                "and_then" => {
+                    if let Some(m) = WHITESPACE.find(source) {
+                        source = &source[m.as_str().len()..];
+                    }
+                    assert_eq!(source.chars().next().unwrap(), '{');
+                    source = &source[1..];
+                    parse_scopes.push(ParseScope::AndThen { old_ops: ops });
+                    ops = vec![Op::EnterScope];
+                    constants.push(HashMap::new());
+                }
+                "or_else" => {
+                    if let Some(m) = WHITESPACE.find(source) {
+                        source = &source[m.as_str().len()..];
+                    }
+                    assert_eq!(source.chars().next().unwrap(), '{');
+                    source = &source[1..];
+                    parse_scopes.push(ParseScope::OrElse { old_ops: ops });
+                    ops = vec![Op::EnterScope];
+                    constants.push(HashMap::new());
+                }
                 "assert" => ops.push(Op::Assert),
+                "band" => ops.push(Op::BitAnd),
+                "bor" => ops.push(Op::BitOr),
+                "bxor" => ops.push(Op::BitXor),
+                "shl" => ops.push(Op::ShiftLeft),
+                "shr" => ops.push(Op::ShiftRight),
+                "to_int" => ops.push(Op::Convert(Type::Integer)),
+                "to_u8" => ops.push(Op::Convert(Type::U8)),
+                "to_i32" => ops.push(Op::Convert(Type::I32)),
+                "to_u64" => ops.push(Op::Convert(Type::U64)),
+                "to_bytes" => ops.push(Op::Convert(Type::Bytes)),
+                "to_string" => ops.push(Op::Convert(Type::String)),
+                "bytes_len" => ops.push(Op::BytesLength),
+                "bytes_get" => ops.push(Op::BytesGet),
+                "bytes_push" => ops.push(Op::BytesPush),
+                "empty_list" => {
+                    if let Some(m) = WHITESPACE.find(source) {
+                        source = &source[m.as_str().len()..];
+                    }
+                    assert_eq!(source.chars().next().unwrap(), '(');
+                    source = &source[1..];
+                    parse_scopes.push(ParseScope::EmptyList { old_ops: ops });
+                    ops = vec![Op::EnterScope];
+                    constants.push(HashMap::new());
+                }
+                "list_length" => ops.push(Op::ListLength),
+                "list_get" => ops.push(Op::ListGet),
+                "list_push" => ops.push(Op::ListPush),
                 _ => {
                     if let Some(values) = constants
                         .iter()
@@ -207,7 +491,7 @@ pub fn compile_ops(
                 }
             }
         } else if let (true, Some(ParseScope::IfCondition)) = (
-            source.chars().next().unwrap() == '{' && parse_scopes.len() > 0,
+            source.starts_with('{') && !parse_scopes.is_empty(),
             parse_scopes.last(),
         ) {
             source = &source[1..];
@@ -215,8 +499,28 @@ pub fn compile_ops(
             parse_scopes.push(ParseScope::IfThen { old_ops: ops });
             ops = vec![Op::EnterScope];
             constants.push(HashMap::new());
+        } else if let (true, Some(ParseScope::CondGuard { .. })) = (
+            source.starts_with('{') && !parse_scopes.is_empty(),
+            parse_scopes.last(),
+        ) {
+            source = &source[1..];
+            ops.push(Op::ExitScope);
+            constants.pop();
+            let (arms, old_ops) =
+                if let ParseScope::CondGuard { arms, old_ops } = parse_scopes.pop().unwrap() {
+                    (arms, old_ops)
+                } else {
+                    unreachable!()
+                };
+            parse_scopes.push(ParseScope::CondArm {
+                guard_ops: ops,
+                arms,
+                old_ops,
+            });
+            ops = vec![Op::EnterScope];
+            constants.push(HashMap::new());
         } else if let (true, Some(ParseScope::WhileCondition { .. })) = (
-            source.chars().next().unwrap() == '{' && parse_scopes.len() > 0,
+            source.starts_with('{') && !parse_scopes.is_empty(),
             parse_scopes.last(),
         ) {
             source = &source[1..];
@@ -234,7 +538,7 @@ pub fn compile_ops(
             });
             ops = vec![Op::EnterScope];
             constants.push(HashMap::new());
-        } else if source.chars().next().unwrap() == ')' && parse_scopes.len() > 0 {
+        } else if source.starts_with(')') && !parse_scopes.is_empty() {
             source = &source[1..];
             match parse_scopes.pop().unwrap() {
                 ParseScope::Over { old_ops } => {
@@ -250,7 +554,12 @@ pub fn compile_ops(
                         );
                     }
                     let mut values = vec![];
-                    execute(&ops, &mut values, builtin_var_values.clone());
+                    execute(
+                        &Rc::new(lower(&ops, &builtin_var_names)),
+                        &mut values,
+                        builtin_vars.clone(),
+                    )
+                    .unwrap_or_else(|error| panic!("{error}"));
                     let offsets = values
                         .into_iter()
                         .map(|value| match value {
@@ -267,6 +576,60 @@ pub fn compile_ops(
                     ops = old_ops;
                     ops.push(Op::Over(offsets));
                 }
+                ParseScope::Pick { old_ops } => {
+                    ops.push(Op::ExitScope);
+                    constants.pop();
+                    let mut type_stack = vec![];
+                    type_check(&ops, &mut type_stack, builtin_var_types.clone());
+                    assert_eq!(
+                        type_stack,
+                        [Type::Integer],
+                        "Expected exactly one integer as the pick depth"
+                    );
+                    let mut values = vec![];
+                    execute(
+                        &Rc::new(lower(&ops, &builtin_var_names)),
+                        &mut values,
+                        builtin_vars.clone(),
+                    )
+                    .unwrap_or_else(|error| panic!("{error}"));
+                    let depth = match values[0] {
+                        Value::Integer(value) => {
+                            assert!(value >= 0, "pick depth must be positive but got {value}");
+                            value as usize
+                        }
+                        _ => unreachable!(),
+                    };
+                    ops = old_ops;
+                    ops.push(Op::Pick(depth));
+                }
+                ParseScope::Roll { old_ops } => {
+                    ops.push(Op::ExitScope);
+                    constants.pop();
+                    let mut type_stack = vec![];
+                    type_check(&ops, &mut type_stack, builtin_var_types.clone());
+                    assert_eq!(
+                        type_stack,
+                        [Type::Integer],
+                        "Expected exactly one integer as the roll depth"
+                    );
+                    let mut values = vec![];
+                    execute(
+                        &Rc::new(lower(&ops, &builtin_var_names)),
+                        &mut values,
+                        builtin_vars.clone(),
+                    )
+                    .unwrap_or_else(|error| panic!("{error}"));
+                    let depth = match values[0] {
+                        Value::Integer(value) => {
+                            assert!(value >= 0, "roll depth must be positive but got {value}");
+                            value as usize
+                        }
+                        _ => unreachable!(),
+                    };
+                    ops = old_ops;
+                    ops.push(Op::Roll(depth));
+                }
                 ParseScope::Var { old_ops } => {
                     ops.push(Op::ExitScope);
                     constants.pop();
@@ -280,7 +643,12 @@ pub fn compile_ops(
                         );
                     }
                     let mut values = vec![];
-                    execute(&ops, &mut values, builtin_var_values.clone());
+                    execute(
+                        &Rc::new(lower(&ops, &builtin_var_names)),
+                        &mut values,
+                        builtin_vars.clone(),
+                    )
+                    .unwrap_or_else(|error| panic!("{error}"));
                     let names = values
                         .into_iter()
                         .map(|value| match value {
@@ -304,7 +672,12 @@ pub fn compile_ops(
                         );
                     }
                     let mut values = vec![];
-                    execute(&ops, &mut values, builtin_var_values.clone());
+                    execute(
+                        &Rc::new(lower(&ops, &builtin_var_names)),
+                        &mut values,
+                        builtin_vars.clone(),
+                    )
+                    .unwrap_or_else(|error| panic!("{error}"));
                     let names = values
                         .into_iter()
                         .map(|value| match value {
@@ -315,7 +688,7 @@ pub fn compile_ops(
                     ops = old_ops;
                     ops.push(Op::GetLocals(names));
                 }
-                ParseScope::ProcTypeParameterTypes { old_ops } => {
+                ParseScope::ProcTypeParameterTypes { coroutine, old_ops } => {
                     ops.push(Op::ExitScope);
                     constants.pop();
                     let mut type_stack = vec![];
@@ -328,7 +701,12 @@ pub fn compile_ops(
                         );
                     }
                     let mut values = vec![];
-                    execute(&ops, &mut values, builtin_var_values.clone());
+                    execute(
+                        &Rc::new(lower(&ops, &builtin_var_names)),
+                        &mut values,
+                        builtin_vars.clone(),
+                    )
+                    .unwrap_or_else(|error| panic!("{error}"));
                     let parameter_types = values
                         .into_iter()
                         .map(|value| match value {
@@ -339,6 +717,7 @@ pub fn compile_ops(
                     let arrow = PROCEDURE_ARROW.find(source).unwrap();
                     source = &source[arrow.as_str().len()..];
                     parse_scopes.push(ParseScope::ProcTypeReturnTypes {
+                        coroutine,
                         parameter_types,
                         old_ops,
                     });
@@ -346,6 +725,7 @@ pub fn compile_ops(
                     constants.push(HashMap::new());
                 }
                 ParseScope::ProcTypeReturnTypes {
+                    coroutine,
                     parameter_types,
                     old_ops,
                 } => {
@@ -361,7 +741,12 @@ pub fn compile_ops(
                         );
                     }
                     let mut values = vec![];
-                    execute(&ops, &mut values, builtin_var_values.clone());
+                    execute(
+                        &Rc::new(lower(&ops, &builtin_var_names)),
+                        &mut values,
+                        builtin_vars.clone(),
+                    )
+                    .unwrap_or_else(|error| panic!("{error}"));
                     let return_types = values
                         .into_iter()
                         .map(|value| match value {
@@ -370,9 +755,16 @@ pub fn compile_ops(
                         })
                         .collect();
                     ops = old_ops;
-                    ops.push(Op::Push(Value::Type(Type::Procedure {
-                        arguments: parameter_types,
-                        return_values: return_types,
+                    ops.push(Op::Push(Value::Type(if coroutine {
+                        Type::Coroutine {
+                            resume_values: parameter_types,
+                            yield_values: return_types,
+                        }
+                    } else {
+                        Type::Procedure {
+                            arguments: parameter_types,
+                            return_values: return_types,
+                        }
                     })));
                 }
                 ParseScope::ProcParameterTypes { old_ops } => {
@@ -388,7 +780,12 @@ pub fn compile_ops(
                         );
                     }
                     let mut values = vec![];
-                    execute(&ops, &mut values, builtin_var_values.clone());
+                    execute(
+                        &Rc::new(lower(&ops, &builtin_var_names)),
+                        &mut values,
+                        builtin_vars.clone(),
+                    )
+                    .unwrap_or_else(|error| panic!("{error}"));
                     let parameter_types = values
                         .into_iter()
                         .map(|value| match value {
@@ -421,7 +818,12 @@ pub fn compile_ops(
                         );
                     }
                     let mut values = vec![];
-                    execute(&ops, &mut values, builtin_var_values.clone());
+                    execute(
+                        &Rc::new(lower(&ops, &builtin_var_names)),
+                        &mut values,
+                        builtin_vars.clone(),
+                    )
+                    .unwrap_or_else(|error| panic!("{error}"));
                     let return_types = values
                         .into_iter()
                         .map(|value| match value {
@@ -456,13 +858,163 @@ pub fn compile_ops(
                 ParseScope::WhileBody { .. } => {
                     panic!("Cannot use ')' to close a while body")
                 }
+                ParseScope::ForName { each, old_ops } => {
+                    ops.push(Op::ExitScope);
+                    constants.pop();
+                    let mut type_stack = vec![];
+                    type_check(&ops, &mut type_stack, builtin_var_types.clone());
+                    assert_eq!(
+                        type_stack,
+                        [Type::Label],
+                        "Expected exactly one label as the for loop variable name"
+                    );
+                    let mut values = vec![];
+                    execute(
+                        &Rc::new(lower(&ops, &builtin_var_names)),
+                        &mut values,
+                        builtin_vars.clone(),
+                    )
+                    .unwrap_or_else(|error| panic!("{error}"));
+                    let name = match values.pop().unwrap() {
+                        Value::Label(value) => value,
+                        _ => unreachable!(),
+                    };
+                    if let Some(m) = WHITESPACE.find(source) {
+                        source = &source[m.as_str().len()..];
+                    }
+                    assert_eq!(source.chars().next().unwrap(), '{');
+                    source = &source[1..];
+                    parse_scopes.push(ParseScope::ForBody {
+                        each,
+                        name,
+                        old_ops,
+                    });
+                    ops = vec![Op::EnterScope];
+                    constants.push(HashMap::new());
+                }
+                ParseScope::ForBody { .. } => {
+                    panic!("Cannot use ')' to close a for body")
+                }
+                ParseScope::TryBody { .. } => {
+                    panic!("Cannot use ')' to close a try body")
+                }
+                ParseScope::CatchType { body_ops, old_ops } => {
+                    ops.push(Op::ExitScope);
+                    constants.pop();
+                    let mut type_stack = vec![];
+                    type_check(&ops, &mut type_stack, builtin_var_types.clone());
+                    assert_eq!(
+                        type_stack,
+                        [Type::Type],
+                        "Expected exactly one type to catch"
+                    );
+                    let mut values = vec![];
+                    execute(
+                        &Rc::new(lower(&ops, &builtin_var_names)),
+                        &mut values,
+                        builtin_vars.clone(),
+                    )
+                    .unwrap_or_else(|error| panic!("{error}"));
+                    let catch_type = match values.pop().unwrap() {
+                        Value::Type(typ) => typ,
+                        _ => unreachable!(),
+                    };
+                    if let Some(m) = WHITESPACE.find(source) {
+                        source = &source[m.as_str().len()..];
+                    }
+                    assert_eq!(source.chars().next().unwrap(), '{');
+                    source = &source[1..];
+                    parse_scopes.push(ParseScope::CatchBody {
+                        body_ops,
+                        catch_type,
+                        old_ops,
+                    });
+                    ops = vec![Op::EnterScope];
+                    constants.push(HashMap::new());
+                }
+                ParseScope::CatchBody { .. } => {
+                    panic!("Cannot use ')' to close a catch body")
+                }
+                ParseScope::CondGuard { .. } => {
+                    panic!("Cannot use ')' to close a cond guard")
+                }
+                ParseScope::CondArm { .. } => {
+                    panic!("Cannot use ')' to close a cond arm")
+                }
+                ParseScope::CondElse { .. } => {
+                    panic!("Cannot use ')' to close the else arm of a cond")
+                }
+                ParseScope::SwitchCases { .. } => {
+                    panic!("Cannot use ')' to close a switch")
+                }
+                ParseScope::SwitchCaseValues { old_ops } => {
+                    ops.push(Op::ExitScope);
+                    constants.pop();
+                    let mut type_stack = vec![];
+                    type_check(&ops, &mut type_stack, builtin_var_types.clone());
+                    assert!(
+                        !type_stack.is_empty(),
+                        "Expected at least 1 value for a switch case"
+                    );
+                    let mut values = vec![];
+                    execute(
+                        &Rc::new(lower(&ops, &builtin_var_names)),
+                        &mut values,
+                        builtin_vars.clone(),
+                    )
+                    .unwrap_or_else(|error| panic!("{error}"));
+                    if let Some(m) = WHITESPACE.find(source) {
+                        source = &source[m.as_str().len()..];
+                    }
+                    assert_eq!(source.chars().next().unwrap(), '{');
+                    source = &source[1..];
+                    parse_scopes.push(ParseScope::SwitchCaseBody { values, old_ops });
+                    ops = vec![Op::EnterScope];
+                    constants.push(HashMap::new());
+                }
+                ParseScope::SwitchCaseBody { .. } => {
+                    panic!("Cannot use ')' to close a switch case")
+                }
+                ParseScope::SwitchElse { .. } => {
+                    panic!("Cannot use ')' to close the else case of a switch")
+                }
+                ParseScope::AndThen { .. } => {
+                    panic!("Cannot use ')' to close an and_then body")
+                }
+                ParseScope::OrElse { .. } => {
+                    panic!("Cannot use ')' to close an or_else body")
+                }
+                ParseScope::EmptyList { old_ops } => {
+                    ops.push(Op::ExitScope);
+                    constants.pop();
+                    let mut type_stack = vec![];
+                    type_check(&ops, &mut type_stack, builtin_var_types.clone());
+                    assert_eq!(
+                        type_stack,
+                        [Type::Type],
+                        "Expected exactly one type for the elements of an empty list"
+                    );
+                    let mut values = vec![];
+                    execute(
+                        &Rc::new(lower(&ops, &builtin_var_names)),
+                        &mut values,
+                        builtin_vars.clone(),
+                    )
+                    .unwrap_or_else(|error| panic!("{error}"));
+                    let element_type = match values.pop().unwrap() {
+                        Value::Type(typ) => typ,
+                        _ => unreachable!(),
+                    };
+                    ops = old_ops;
+                    ops.push(Op::Push(Value::List(element_type, vec![])));
+                }
                 ParseScope::Const { old_ops } => {
                     ops.push(Op::ExitScope);
                     constants.pop();
                     let mut type_stack = vec![];
                     type_check(&ops, &mut type_stack, builtin_var_types.clone());
                     assert!(
-                        type_stack.len() >= 1,
+                        !type_stack.is_empty(),
                         "Expected at least 1 element on the type stack"
                     );
                     assert_eq!(
@@ -472,28 +1024,40 @@ pub fn compile_ops(
                         type_stack[0]
                     );
                     let mut values = vec![];
-                    execute(&ops, &mut values, builtin_var_values.clone());
+                    execute(
+                        &Rc::new(lower(&ops, &builtin_var_names)),
+                        &mut values,
+                        builtin_vars.clone(),
+                    )
+                    .unwrap_or_else(|error| panic!("{error}"));
                     let name = match &values[0] {
                         Value::Label(value) => value,
                         _ => unreachable!(),
                     };
                     let values = &values[1..];
-                    if let Some(_) = constants
+                    if constants
                         .last_mut()
                         .unwrap()
-                        .insert(name.clone(), values.iter().cloned().collect())
+                        .insert(name.clone(), values.to_vec())
+                        .is_some()
                     {
                         panic!("Redeclaration of constant '{name}'");
                     }
                     ops = old_ops;
                 }
             }
-        } else if source.chars().next().unwrap() == '}' && parse_scopes.len() > 0 {
+        } else if source.starts_with('}') && !parse_scopes.is_empty() {
             source = &source[1..];
             match parse_scopes.pop().unwrap() {
                 ParseScope::Over { .. } => {
                     panic!("Cannot use '}}' to close an over");
                 }
+                ParseScope::Pick { .. } => {
+                    panic!("Cannot use '}}' to close a pick");
+                }
+                ParseScope::Roll { .. } => {
+                    panic!("Cannot use '}}' to close a roll");
+                }
                 ParseScope::Var { .. } => {
                     panic!("Cannot use '}}' to close a var");
                 }
@@ -544,8 +1108,8 @@ pub fn compile_ops(
                         let then_ops = ops;
                         ops = old_ops;
                         ops.push(Op::If {
-                            then: then_ops,
-                            r#else: vec![],
+                            then: Rc::new(then_ops),
+                            r#else: Rc::new(vec![]),
                         });
                     }
                 }
@@ -556,8 +1120,8 @@ pub fn compile_ops(
                     let else_ops = ops;
                     ops = old_ops;
                     ops.push(Op::If {
-                        then: then_ops,
-                        r#else: else_ops,
+                        then: Rc::new(then_ops),
+                        r#else: Rc::new(else_ops),
                     });
                 }
                 ParseScope::WhileCondition { .. } => {
@@ -572,13 +1136,172 @@ pub fn compile_ops(
                     let body_ops = ops;
                     ops = old_ops;
                     ops.push(Op::While {
-                        condition: condition_ops,
-                        body: body_ops,
+                        condition: Rc::new(condition_ops),
+                        body: Rc::new(body_ops),
                     });
                 }
                 ParseScope::Const { .. } => {
                     panic!("Cannot use '}}' to close a const");
                 }
+                ParseScope::ForName { .. } => {
+                    panic!("Cannot use '}}' to close a for loop variable name");
+                }
+                ParseScope::TryBody { old_ops } => {
+                    ops.push(Op::ExitScope);
+                    constants.pop();
+                    let m = CATCH
+                        .find(source)
+                        .expect("Expected a catch after the try body");
+                    source = &source[m.as_str().len()..];
+                    parse_scopes.push(ParseScope::CatchType {
+                        body_ops: ops,
+                        old_ops,
+                    });
+                    ops = vec![Op::EnterScope];
+                    constants.push(HashMap::new());
+                }
+                ParseScope::CatchType { .. } => {
+                    panic!("Cannot use '}}' to close a catch type");
+                }
+                ParseScope::CatchBody {
+                    body_ops,
+                    catch_type,
+                    old_ops,
+                } => {
+                    ops.push(Op::ExitScope);
+                    constants.pop();
+                    let handler_ops = ops;
+                    ops = old_ops;
+                    ops.push(Op::Try {
+                        body: Rc::new(body_ops),
+                        catch_type,
+                        handler: Rc::new(handler_ops),
+                    });
+                }
+                ParseScope::CondGuard { arms, old_ops } => {
+                    assert_eq!(ops.len(), 1, "Expected '{{' after a cond guard");
+                    constants.pop();
+                    ops = old_ops;
+                    ops.push(Op::Cond {
+                        arms,
+                        default: None,
+                    });
+                }
+                ParseScope::CondArm {
+                    guard_ops,
+                    mut arms,
+                    old_ops,
+                } => {
+                    ops.push(Op::ExitScope);
+                    constants.pop();
+                    arms.push((Rc::new(guard_ops), Rc::new(ops)));
+                    if let Some(m) = ELSE.find(source) {
+                        source = &source[m.as_str().len()..];
+                        parse_scopes.push(ParseScope::CondElse { arms, old_ops });
+                    } else {
+                        parse_scopes.push(ParseScope::CondGuard { arms, old_ops });
+                    }
+                    ops = vec![Op::EnterScope];
+                    constants.push(HashMap::new());
+                }
+                ParseScope::CondElse { arms, old_ops } => {
+                    ops.push(Op::ExitScope);
+                    constants.pop();
+                    let m = CLOSE_BRACE
+                        .find(source)
+                        .expect("Expected '}' to close the cond after its else arm");
+                    source = &source[m.as_str().len()..];
+                    let default_ops = ops;
+                    ops = old_ops;
+                    ops.push(Op::Cond {
+                        arms,
+                        default: Some(Rc::new(default_ops)),
+                    });
+                }
+                ParseScope::SwitchCases { cases, old_ops } => {
+                    assert!(ops.is_empty(), "Only cases can be inside a switch");
+                    ops = old_ops;
+                    ops.push(Op::Switch {
+                        cases,
+                        default: None,
+                    });
+                }
+                ParseScope::SwitchCaseValues { .. } => {
+                    panic!("Cannot use '}}' to close switch case values");
+                }
+                ParseScope::SwitchCaseBody { values, old_ops } => {
+                    ops.push(Op::ExitScope);
+                    constants.pop();
+                    let body_ops = ops;
+                    ops = old_ops;
+                    let cases = match parse_scopes.last_mut() {
+                        Some(ParseScope::SwitchCases { cases, .. }) => cases,
+                        _ => unreachable!(),
+                    };
+                    cases.push((values, Rc::new(body_ops)));
+                    if let Some(m) = ELSE.find(source) {
+                        source = &source[m.as_str().len()..];
+                        let (cases, old_ops) = match parse_scopes.pop().unwrap() {
+                            ParseScope::SwitchCases { cases, old_ops } => (cases, old_ops),
+                            _ => unreachable!(),
+                        };
+                        parse_scopes.push(ParseScope::SwitchElse { cases, old_ops });
+                        ops = vec![Op::EnterScope];
+                        constants.push(HashMap::new());
+                    }
+                }
+                ParseScope::SwitchElse { cases, old_ops } => {
+                    ops.push(Op::ExitScope);
+                    constants.pop();
+                    let m = CLOSE_BRACE
+                        .find(source)
+                        .expect("Expected '}' to close the switch after its else case");
+                    source = &source[m.as_str().len()..];
+                    let default_ops = ops;
+                    ops = old_ops;
+                    ops.push(Op::Switch {
+                        cases,
+                        default: Some(Rc::new(default_ops)),
+                    });
+                }
+                ParseScope::ForBody {
+                    each,
+                    name,
+                    old_ops,
+                } => {
+                    ops.push(Op::ExitScope);
+                    constants.pop();
+                    let body_ops = ops;
+                    ops = old_ops;
+                    ops.push(if each {
+                        Op::ForEach {
+                            name,
+                            body: Rc::new(body_ops),
+                        }
+                    } else {
+                        Op::ForRange {
+                            name,
+                            body: Rc::new(body_ops),
+                        }
+                    });
+                }
+                ParseScope::AndThen { old_ops } => {
+                    ops.push(Op::ExitScope);
+                    constants.pop();
+                    let body_ops = ops;
+                    ops = old_ops;
+                    ops.push(Op::AndThen(Rc::new(body_ops)));
+                }
+                ParseScope::OrElse { old_ops } => {
+                    ops.push(Op::ExitScope);
+                    constants.pop();
+                    let body_ops = ops;
+                    ops = old_ops;
+                    ops.push(Op::OrElse(Rc::new(body_ops)));
+                }
+                ParseScope::EmptyList { .. } => {
+                    panic!("Cannot use '}}' to close an empty list type");
+                }
             }
         } else {
             panic!("Unexpected character {:?}", source.chars().next().unwrap());
@@ -587,6 +1310,6 @@ pub fn compile_ops(
     ops.push(Op::ExitScope);
     constants.pop();
     assert_eq!(constants.len(), 0);
-    type_check(&ops, &mut vec![], builtin_var_types);
+    type_check(&ops, &mut stack.to_vec(), builtin_var_types);
     ops
 }
@@ -0,0 +1,42 @@
+use crate::VmBuilder;
+
+/// Working with chars, which are Unicode scalar values
+pub fn character(vm: VmBuilder) -> VmBuilder {
+    vm.register("char_to_code", |character: char| character as i64)
+        .register("char_from_code", |code: i64| {
+            u32::try_from(code)
+                .ok()
+                .and_then(char::from_u32)
+                .ok_or_else(|| format!("{code} is not the code point of a char"))
+        })
+        .register("char_is_digit", |character: char| {
+            character.is_ascii_digit()
+        })
+        .register("char_is_alphabetic", |character: char| {
+            character.is_alphabetic()
+        })
+        .register("char_is_whitespace", |character: char| {
+            character.is_whitespace()
+        })
+        .register("char_upper", |character: char| {
+            single_char(character.to_uppercase()).unwrap_or(character)
+        })
+        .register("char_lower", |character: char| {
+            single_char(character.to_lowercase()).unwrap_or(character)
+        })
+        .register("string_push", |mut string: String, character: char| {
+            string.push(character);
+            string
+        })
+        .register("string_chars", |string: String| {
+            string.chars().collect::<Vec<_>>()
+        })
+}
+
+/// Some chars change case into more than one, like 'ß' into "SS", those are left as they are
+fn single_char(mut chars: impl Iterator<Item = char>) -> Option<char> {
+    match (chars.next(), chars.next()) {
+        (Some(character), None) => Some(character),
+        _ => None,
+    }
+}
@@ -0,0 +1,25 @@
+use crate::VmBuilder;
+
+/// Working with bytes as a whole, indices count from 0 like `bytes_get`
+pub fn collections(vm: VmBuilder) -> VmBuilder {
+    vm.register(
+        "bytes_slice",
+        |bytes: Vec<u8>, start: i64, end: i64| match (usize::try_from(start), usize::try_from(end))
+        {
+            (Ok(start), Ok(end)) if start <= end && end <= bytes.len() => {
+                Ok(bytes[start..end].to_vec())
+            }
+            _ => Err(format!(
+                "Cannot slice {start}..{end} out of bytes of length {}",
+                bytes.len()
+            )),
+        },
+    )
+    .register("bytes_reverse", |mut bytes: Vec<u8>| {
+        bytes.reverse();
+        bytes
+    })
+    .register("bytes_contains", |bytes: Vec<u8>, byte: u8| {
+        bytes.contains(&byte)
+    })
+}
@@ -0,0 +1,19 @@
+use std::io::Write;
+
+use crate::{Type, VmBuilder};
+
+/// Printing to stdout and reading lines from stdin
+pub fn io(vm: VmBuilder) -> VmBuilder {
+    vm.register("println", || println!())
+        .register("print_type", |typ: Type| println!("{typ}"))
+        .register("print_int", |value: i64| println!("{value}"))
+        .register("print_bool", |value: bool| println!("{value}"))
+        .register("print_string", |value: String| {
+            print!("{value}");
+            std::io::stdout().flush().unwrap();
+        })
+        .register("read_line", || match std::io::stdin().lines().next() {
+            Some(Ok(line)) => Ok(line),
+            _ => Err("Unable to read a line".to_string()),
+        })
+}
@@ -0,0 +1,39 @@
+use crate::VmBuilder;
+
+/// Integer arithmetic beyond what the built in words do, overflowing wraps like `add` and `mul`
+pub fn math(vm: VmBuilder) -> VmBuilder {
+    vm.register("abs", |value: i64| value.wrapping_abs())
+        .register("min", |a: i64, b: i64| a.min(b))
+        .register("max", |a: i64, b: i64| a.max(b))
+        .register("clamp", |value: i64, low: i64, high: i64| {
+            if low > high {
+                return Err("The low end of a clamp cannot be above the high end".to_string());
+            }
+            Ok(value.clamp(low, high))
+        })
+        .register("pow", |base: i64, exponent: i64| {
+            if exponent < 0 {
+                return Err("Cannot raise to a negative power".to_string());
+            }
+            Ok(wrapping_pow(base, exponent as u64))
+        })
+        .register("sqrt", |value: i64| {
+            if value < 0 {
+                return Err("Cannot take the square root of a negative number".to_string());
+            }
+            Ok(value.isqrt())
+        })
+}
+
+/// `i64::wrapping_pow` for exponents too big for a `u32`
+fn wrapping_pow(mut base: i64, mut exponent: u64) -> i64 {
+    let mut result: i64 = 1;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = result.wrapping_mul(base);
+        }
+        base = base.wrapping_mul(base);
+        exponent >>= 1;
+    }
+    result
+}
@@ -0,0 +1,21 @@
+//! Builtins that hosts can add to a `Vm` a module at a time, the binary uses all of them
+
+mod character;
+mod collections;
+mod io;
+mod math;
+mod string;
+
+pub use character::character;
+pub use collections::collections;
+pub use io::io;
+pub use math::math;
+pub use string::string;
+
+use crate::VmBuilder;
+
+/// Adds a set of builtins to a `Vm` that is being built
+pub type Module = fn(VmBuilder) -> VmBuilder;
+
+/// Every module, in the order `VmBuilder::stdlib` adds them
+pub const ALL: [Module; 5] = [io, string, character, math, collections];
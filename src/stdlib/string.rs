@@ -0,0 +1,74 @@
+use crate::VmBuilder;
+
+/// Working with strings, indices count chars from 0 rather than bytes
+pub fn string(vm: VmBuilder) -> VmBuilder {
+    vm.register("string_length", |string: String| {
+        string.chars().count() as i64
+    })
+    .register("string_get", |string: String, index: i64| {
+        usize::try_from(index)
+            .ok()
+            .and_then(|index| string.chars().nth(index))
+            .ok_or_else(|| out_of_range(&string, index))
+    })
+    .register("substring", |string: String, start: i64, end: i64| {
+        let length = string.chars().count();
+        match (usize::try_from(start), usize::try_from(end)) {
+            (Ok(start), Ok(end)) if start <= end && end <= length => Ok(string
+                .chars()
+                .skip(start)
+                .take(end - start)
+                .collect::<String>()),
+            _ => Err(format!(
+                "Cannot take {start}..{end} out of a string of length {length}"
+            )),
+        }
+    })
+    // also pushes whether it was found, the index is 0 when it wasn't
+    .register(
+        "string_find",
+        |string: String, pattern: String| match string.find(&pattern) {
+            Some(byte_index) => (string[..byte_index].chars().count() as i64, true),
+            None => (0, false),
+        },
+    )
+    .register(
+        "string_replace",
+        |string: String, from: String, to: String| string.replace(&from, &to),
+    )
+    .register("string_split", |string: String, separator: String| {
+        if separator.is_empty() {
+            return Err("Cannot split on an empty separator".to_string());
+        }
+        Ok(string
+            .split(&separator)
+            .map(str::to_string)
+            .collect::<Vec<_>>())
+    })
+    .register("string_join", |strings: Vec<String>, separator: String| {
+        strings.join(&separator)
+    })
+    .register("string_trim", |string: String| string.trim().to_string())
+    .register("string_upper", |string: String| string.to_uppercase())
+    .register("string_lower", |string: String| string.to_lowercase())
+    .register("string_starts_with", |string: String, prefix: String| {
+        string.starts_with(&prefix)
+    })
+    .register("string_ends_with", |string: String, suffix: String| {
+        string.ends_with(&suffix)
+    })
+    .register("int_to_string", |value: i64| value.to_string())
+    .register("string_to_int", |string: String| {
+        string
+            .trim()
+            .parse::<i64>()
+            .map_err(|error| format!("Cannot convert {string:?} to an int: {error}"))
+    })
+}
+
+fn out_of_range(string: &str, index: i64) -> String {
+    format!(
+        "Index {index} is out of range for a string of length {}",
+        string.chars().count()
+    )
+}
@@ -11,11 +11,33 @@ pub enum Type {
     Boolean,
     Character,
     Integer,
+    U8,
+    I32,
+    U64,
+    Bytes,
     Procedure {
         arguments: Vec<Type>,
         return_values: Vec<Type>,
     },
     Reference(Box<Type>),
+    /// A list with elements of the type
+    List(Box<Type>),
+    Coroutine {
+        resume_values: Vec<Type>,
+        yield_values: Vec<Type>,
+    },
+    /// A host type that programs can only pass around, told apart from other host types by name
+    Opaque(String),
+}
+
+impl Type {
+    pub fn is_integer(&self) -> bool {
+        matches!(self, Type::Integer | Type::U8 | Type::I32 | Type::U64)
+    }
+
+    pub fn is_ordered(&self) -> bool {
+        self.is_integer() || matches!(self, Type::String | Type::Boolean | Type::Character)
+    }
 }
 
 impl std::fmt::Display for Type {
@@ -28,6 +50,10 @@ impl std::fmt::Display for Type {
             Type::Boolean => write!(f, "bool"),
             Type::Character => write!(f, "char"),
             Type::Integer => write!(f, "int"),
+            Type::U8 => write!(f, "u8"),
+            Type::I32 => write!(f, "i32"),
+            Type::U64 => write!(f, "u64"),
+            Type::Bytes => write!(f, "bytes"),
             Type::Procedure {
                 arguments,
                 return_values,
@@ -49,17 +75,71 @@ impl std::fmt::Display for Type {
                 write!(f, ")")
             }
             Type::Reference(referered_type) => write!(f, "{referered_type} ref"),
+            Type::List(element_type) => write!(f, "{element_type} list"),
+            Type::Coroutine {
+                resume_values,
+                yield_values,
+            } => {
+                write!(f, "coroutine_type(")?;
+                for (i, resume_value) in resume_values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{resume_value}")?;
+                }
+                write!(f, ") -> (")?;
+                for (i, yield_value) in yield_values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{yield_value}")?;
+                }
+                write!(f, ")")
+            }
+            Type::Opaque(name) => write!(f, "{name}"),
         }
     }
 }
 
+/// The stacks that `break`, `continue`, `return` and `yield` must leave behind when they are reached
+#[derive(Clone, Default)]
+struct ExitStacks {
+    loop_stack: Option<Vec<Type>>,
+    return_types: Option<Vec<Type>>,
+    /// The procedure's arguments, which are what a `yield` gets back when the coroutine is resumed
+    resume_types: Option<Vec<Type>>,
+    /// The types under the body of a `try`, which it can't touch since a throw can't put them back
+    hidden: Vec<Type>,
+}
+
+impl ExitStacks {
+    /// The whole stack, with the hidden types under the ones the block can see
+    fn whole_stack(&self, stack: &[Type]) -> Vec<Type> {
+        [&self.hidden[..], stack].concat()
+    }
+}
+
 pub fn type_check<'a>(
     ops: impl IntoIterator<Item = &'a Op>,
     stack: &mut Vec<Type>,
     locals: HashMap<String, Type>,
 ) {
+    type_check_block(ops, stack, locals, &ExitStacks::default());
+}
+
+/// Returns true if every path through the ops leaves the block through a `break`, `continue` or `return`
+fn type_check_block<'a>(
+    ops: impl IntoIterator<Item = &'a Op>,
+    stack: &mut Vec<Type>,
+    locals: HashMap<String, Type>,
+    exits: &ExitStacks,
+) -> bool {
     let mut locals = vec![locals];
+    let mut exited_by = None;
     for op in ops {
+        if let (Some(word), false) = (exited_by, matches!(op, Op::ExitScope)) {
+            panic!("Unreachable code after '{word}'");
+        }
         match op {
             Op::DumpCurrentTypeStackInTypeChecking => {
                 println!("Current types on the stack:");
@@ -72,14 +152,17 @@ pub fn type_check<'a>(
                 stack.push(value.get_type());
             }
             Op::Dup => {
-                assert!(stack.len() >= 1, "Expected at least 1 element to duplicate");
+                assert!(
+                    !stack.is_empty(),
+                    "Expected at least 1 element to duplicate"
+                );
                 let value = stack.pop().unwrap();
                 stack.push(value.clone());
                 stack.push(value);
             }
             Op::Drop => {
                 assert!(
-                    stack.len() >= 1,
+                    !stack.is_empty(),
                     "Expected at least 1 element to drop from the stack"
                 );
                 stack.pop();
@@ -97,12 +180,7 @@ pub fn type_check<'a>(
                 }
             }
             Op::MakeProcedure { typ, ops } => {
-                let mut current_locals = HashMap::new();
-                for (name, local) in locals.iter().rev().flatten() {
-                    if !current_locals.contains_key(name) {
-                        current_locals.insert(name.clone(), local.clone());
-                    }
-                }
+                let current_locals = visible_locals(&locals);
                 let (arguments, return_values) = match typ {
                     Type::Procedure {
                         arguments,
@@ -111,31 +189,89 @@ pub fn type_check<'a>(
                     _ => panic!("Expected procedure type but got type '{typ}'"),
                 };
                 let mut func_stack = arguments.clone();
-                type_check(ops.iter(), &mut func_stack, current_locals);
-                assert_eq!(&func_stack, return_values); // TODO: error message
+                let exits = ExitStacks {
+                    return_types: Some(return_values.clone()),
+                    resume_types: Some(arguments.clone()),
+                    ..Default::default()
+                };
+                if !type_check_block(ops.iter(), &mut func_stack, current_locals, &exits) {
+                    assert_eq!(&func_stack, return_values); // TODO: error message
+                }
                 stack.push(typ.clone());
             }
+            Op::Swap => {
+                expect_depth(stack, 2, "swap");
+                let len = stack.len();
+                stack.swap(len - 1, len - 2);
+            }
+            Op::Rot => {
+                expect_depth(stack, 3, "rot");
+                let len = stack.len();
+                stack[len - 3..].rotate_left(1);
+            }
+            Op::ReverseRot => {
+                expect_depth(stack, 3, "-rot");
+                let len = stack.len();
+                stack[len - 3..].rotate_right(1);
+            }
+            Op::Nip => {
+                expect_depth(stack, 2, "nip");
+                stack.remove(stack.len() - 2);
+            }
+            Op::Tuck => {
+                expect_depth(stack, 2, "tuck");
+                let b = stack.last().unwrap().clone();
+                stack.insert(stack.len() - 2, b);
+            }
+            Op::Dup2 => {
+                expect_depth(stack, 2, "2dup");
+                stack.extend_from_within(stack.len() - 2..);
+            }
+            Op::Drop2 => {
+                expect_depth(stack, 2, "2drop");
+                stack.truncate(stack.len() - 2);
+            }
+            Op::Swap2 => {
+                expect_depth(stack, 4, "2swap");
+                let len = stack.len();
+                stack[len - 4..].rotate_left(2);
+            }
+            Op::Pick(depth) => {
+                expect_depth(stack, depth + 1, "pick");
+                let typ = stack[stack.len() - depth - 1].clone();
+                stack.push(typ);
+            }
+            Op::Roll(depth) => {
+                expect_depth(stack, depth + 1, "roll");
+                let typ = stack.remove(stack.len() - depth - 1);
+                stack.push(typ);
+            }
+            Op::Dip => {
+                let procedure_type = stack
+                    .pop()
+                    .expect("Expected a procedure to dip on the stack but got nothing");
+                let typ = stack
+                    .pop()
+                    .expect("Expected a value to dip under on the stack but got nothing");
+                call_procedure_type(stack, &procedure_type);
+                stack.push(typ);
+            }
+            Op::Keep => {
+                let procedure_type = stack
+                    .pop()
+                    .expect("Expected a procedure to keep on the stack but got nothing");
+                let typ = stack
+                    .last()
+                    .expect("Expected a value to keep on the stack but got nothing")
+                    .clone();
+                call_procedure_type(stack, &procedure_type);
+                stack.push(typ);
+            }
             Op::Call => {
                 let procedure_type = stack
                     .pop()
                     .expect("Expected a procedure on the stack but got nothing");
-                let (arguments, return_values) = match &procedure_type {
-                    Type::Procedure {
-                        arguments,
-                        return_values,
-                    } => (arguments, return_values),
-                    _ => panic!("Expected a procedure to call but got type '{procedure_type}'"),
-                };
-                for (i, typ) in arguments.iter().enumerate().rev() {
-                    let actual_typ = stack.pop().expect(&format!(
-                        "Expected argument {i} on the stack but got nothing"
-                    ));
-                    assert_eq!(
-                        typ, &actual_typ,
-                        "Expected argument {i} to be type '{typ}' but got '{actual_typ}'"
-                    );
-                }
-                stack.append(&mut return_values.clone());
+                call_procedure_type(stack, &procedure_type);
             }
             Op::Add => {
                 let b = stack
@@ -145,7 +281,7 @@ pub fn type_check<'a>(
                     .pop()
                     .expect("Expected second operand to add on the stack but got nothing");
                 stack.push(match (&a, &b) {
-                    (Type::Integer, Type::Integer) => Type::Integer,
+                    (a, b) if a.is_integer() && a == b => a.clone(),
                     _ => panic!("Cannot add types '{a}' and '{b}'"),
                 });
             }
@@ -157,7 +293,7 @@ pub fn type_check<'a>(
                     .pop()
                     .expect("Expected second operand to subtract on the stack but got nothing");
                 stack.push(match (&a, &b) {
-                    (Type::Integer, Type::Integer) => Type::Integer,
+                    (a, b) if a.is_integer() && a == b => a.clone(),
                     _ => panic!("Cannot subtract types '{a}' and '{b}'"),
                 });
             }
@@ -169,7 +305,7 @@ pub fn type_check<'a>(
                     .pop()
                     .expect("Expected second operand to multiply on the stack but got nothing");
                 stack.push(match (&a, &b) {
-                    (Type::Integer, Type::Integer) => Type::Integer,
+                    (a, b) if a.is_integer() && a == b => a.clone(),
                     _ => panic!("Cannot multiply types '{a}' and '{b}'"),
                 });
             }
@@ -181,9 +317,9 @@ pub fn type_check<'a>(
                     .pop()
                     .expect("Expected second operand to divmod on the stack but got nothing");
                 match (&a, &b) {
-                    (Type::Integer, Type::Integer) => {
-                        stack.push(Type::Integer);
-                        stack.push(Type::Integer);
+                    (a, b) if a.is_integer() && a == b => {
+                        stack.push(a.clone());
+                        stack.push(a.clone());
                     }
                     _ => panic!("Cannot use divmod on types '{a}' and '{b}'"),
                 };
@@ -271,7 +407,7 @@ pub fn type_check<'a>(
                     .pop()
                     .expect("Expected second operand to greater than on the stack but got nothing");
                 stack.push(match (a, b) {
-                    (Type::Integer, Type::Integer) => Type::Boolean,
+                    (a, b) if a.is_ordered() && a == b => Type::Boolean,
                     (a, b) => panic!("Cannot compare greater than on types '{a}' and '{b}'"),
                 });
             }
@@ -283,10 +419,48 @@ pub fn type_check<'a>(
                     .pop()
                     .expect("Expected second operand to less than on the stack but got nothing");
                 stack.push(match (a, b) {
-                    (Type::Integer, Type::Integer) => Type::Boolean,
+                    (a, b) if a.is_ordered() && a == b => Type::Boolean,
                     (a, b) => panic!("Cannot compare less than on types '{a}' and '{b}'"),
                 });
             }
+            Op::GreaterThanOrEqual => {
+                let b = stack.pop().expect(
+                    "Expected first operand to greater than or equal on the stack but got nothing",
+                );
+                let a = stack.pop().expect(
+                    "Expected second operand to greater than or equal on the stack but got nothing",
+                );
+                stack.push(match (a, b) {
+                    (a, b) if a.is_ordered() && a == b => Type::Boolean,
+                    (a, b) => {
+                        panic!("Cannot compare greater than or equal on types '{a}' and '{b}'")
+                    }
+                });
+            }
+            Op::LessThanOrEqual => {
+                let b = stack.pop().expect(
+                    "Expected first operand to less than or equal on the stack but got nothing",
+                );
+                let a = stack.pop().expect(
+                    "Expected second operand to less than or equal on the stack but got nothing",
+                );
+                stack.push(match (a, b) {
+                    (a, b) if a.is_ordered() && a == b => Type::Boolean,
+                    (a, b) => panic!("Cannot compare less than or equal on types '{a}' and '{b}'"),
+                });
+            }
+            Op::Compare => {
+                let b = stack
+                    .pop()
+                    .expect("Expected first operand to compare on the stack but got nothing");
+                let a = stack
+                    .pop()
+                    .expect("Expected second operand to compare on the stack but got nothing");
+                stack.push(match (a, b) {
+                    (a, b) if a.is_ordered() && a == b => Type::Integer,
+                    (a, b) => panic!("Cannot compare types '{a}' and '{b}'"),
+                });
+            }
             Op::Equal => {
                 let b = stack
                     .pop()
@@ -297,6 +471,16 @@ pub fn type_check<'a>(
                 assert_eq!(a, b, "Cannot compare types '{a}' and '{b}'");
                 stack.push(Type::Boolean);
             }
+            Op::NotEqual => {
+                let b = stack
+                    .pop()
+                    .expect("Expected first operand to not equal on the stack but got nothing");
+                let a = stack
+                    .pop()
+                    .expect("Expected second operand to not equal on the stack but got nothing");
+                assert_eq!(a, b, "Cannot compare types '{a}' and '{b}'");
+                stack.push(Type::Boolean);
+            }
             Op::Not => {
                 let typ = stack
                     .pop()
@@ -304,10 +488,10 @@ pub fn type_check<'a>(
                 assert_eq!(typ, Type::Boolean, "Expected a boolean but got '{typ}'");
                 stack.push(Type::Boolean);
             }
-            Op::MakeReferenceType => {
+            Op::MakeReferenceType | Op::MakeListType => {
                 let typ = stack
                     .pop()
-                    .expect("Expected a type to make a reference type from but got nothing");
+                    .expect("Expected a type to make a type from but got nothing");
                 assert_eq!(typ, Type::Type, "Expected a type but got '{typ}'");
                 stack.push(Type::Type);
             }
@@ -320,29 +504,37 @@ pub fn type_check<'a>(
                     Type::Boolean,
                     "Expected a boolean for if condition but got '{condition}'"
                 );
-                let mut current_locals = HashMap::new();
-                for (name, local) in locals.iter().rev().flatten() {
-                    if !current_locals.contains_key(name) {
-                        current_locals.insert(name.clone(), local.clone());
-                    }
-                }
+                let current_locals = visible_locals(&locals);
                 let mut then_stack = stack.clone();
-                type_check(then, &mut then_stack, current_locals.clone());
-                type_check(r#else, stack, current_locals);
-                assert_eq!(
-                    &then_stack, stack,
-                    "Both paths through an if must result in the same types on the stack"
-                );
+                let then_exits =
+                    type_check_block(then.iter(), &mut then_stack, current_locals.clone(), exits);
+                let else_exits = type_check_block(r#else.iter(), stack, current_locals, exits);
+                match (then_exits, else_exits) {
+                    (true, true) => exited_by = Some("if"),
+                    (true, false) => {}
+                    (false, true) => *stack = then_stack,
+                    (false, false) => assert_eq!(
+                        &then_stack, stack,
+                        "Both paths through an if must result in the same types on the stack"
+                    ),
+                }
             }
             Op::While { condition, body } => {
                 let old_stack = stack.clone();
-                let mut current_locals = HashMap::new();
-                for (name, local) in locals.iter().rev().flatten() {
-                    if !current_locals.contains_key(name) {
-                        current_locals.insert(name.clone(), local.clone());
-                    }
+                let current_locals = visible_locals(&locals);
+                let condition_exits = ExitStacks {
+                    loop_stack: None,
+                    ..exits.clone()
+                };
+                if type_check_block(
+                    condition.iter(),
+                    stack,
+                    current_locals.clone(),
+                    &condition_exits,
+                ) {
+                    exited_by = Some("while");
+                    continue;
                 }
-                type_check(condition, stack, current_locals.clone());
                 let condition = stack
                     .pop()
                     .expect("Expected while condition on the stack but got nothing");
@@ -355,20 +547,390 @@ pub fn type_check<'a>(
                     &old_stack, stack,
                     "The number of elements after the while condition must be the same as before the while with an extra boolean on top"
                 );
-                type_check(body, stack, current_locals);
-                assert_eq!(
-                    &old_stack, stack,
-                    "The number of elements after the while body must be the same as before the while"
-                );
+                let body_exits = ExitStacks {
+                    loop_stack: Some(exits.whole_stack(&old_stack)),
+                    ..exits.clone()
+                };
+                if type_check_block(body.iter(), stack, current_locals, &body_exits) {
+                    *stack = old_stack;
+                } else {
+                    assert_eq!(
+                        &old_stack, stack,
+                        "The number of elements after the while body must be the same as before the while"
+                    );
+                }
+            }
+            Op::Cond { arms, default } => {
+                let current_locals = visible_locals(&locals);
+                let old_stack = stack.clone();
+                let mut bodies = vec![];
+                for (guard, body) in arms {
+                    let mut guard_stack = old_stack.clone();
+                    if type_check_block(
+                        guard.iter(),
+                        &mut guard_stack,
+                        current_locals.clone(),
+                        exits,
+                    ) {
+                        panic!("A cond guard cannot always exit");
+                    }
+                    let condition = guard_stack
+                        .pop()
+                        .expect("Expected cond guard result on the stack but got nothing");
+                    assert_eq!(
+                        condition,
+                        Type::Boolean,
+                        "Expected a boolean for cond guard but got '{condition}'"
+                    );
+                    assert_eq!(
+                        old_stack, guard_stack,
+                        "A cond guard must leave the stack as it was with an extra boolean on top"
+                    );
+                    bodies.push(body);
+                }
+                if let Some(default) = default {
+                    bodies.push(default);
+                }
+                let mut result_stack = if default.is_none() {
+                    Some(old_stack.clone())
+                } else {
+                    None
+                };
+                for body in bodies {
+                    let mut body_stack = old_stack.clone();
+                    if type_check_block(body.iter(), &mut body_stack, current_locals.clone(), exits)
+                    {
+                        continue;
+                    }
+                    if let Some(result_stack) = &result_stack {
+                        assert_eq!(
+                            result_stack, &body_stack,
+                            "Every arm of a cond must result in the same types on the stack"
+                        );
+                    } else {
+                        result_stack = Some(body_stack);
+                    }
+                }
+                match result_stack {
+                    Some(result_stack) => *stack = result_stack,
+                    None => exited_by = Some("cond"),
+                }
+            }
+            Op::Switch { cases, default } => {
+                let typ = stack
+                    .pop()
+                    .expect("Expected a value to switch on but got nothing");
+                let current_locals = visible_locals(&locals);
+                let old_stack = stack.clone();
+                let mut bodies = vec![];
+                for (values, body) in cases {
+                    for value in values {
+                        let value_type = value.get_type();
+                        assert_eq!(
+                            value_type, typ,
+                            "Expected switch case value to be type '{typ}' but got '{value_type}'"
+                        );
+                    }
+                    bodies.push(body);
+                }
+                if let Some(default) = default {
+                    bodies.push(default);
+                }
+                let mut result_stack = if default.is_none() {
+                    Some(old_stack.clone())
+                } else {
+                    None
+                };
+                for body in bodies {
+                    let mut body_stack = old_stack.clone();
+                    if type_check_block(body.iter(), &mut body_stack, current_locals.clone(), exits)
+                    {
+                        continue;
+                    }
+                    if let Some(result_stack) = &result_stack {
+                        assert_eq!(
+                            result_stack, &body_stack,
+                            "Every case of a switch must result in the same types on the stack"
+                        );
+                    } else {
+                        result_stack = Some(body_stack);
+                    }
+                }
+                match result_stack {
+                    Some(result_stack) => *stack = result_stack,
+                    None => exited_by = Some("switch"),
+                }
+            }
+            Op::ForRange { name, body } => {
+                for operand in ["step", "end", "start"] {
+                    let typ = stack.pop().unwrap_or_else(|| {
+                        panic!("Expected the for loop {operand} on the stack but got nothing")
+                    });
+                    assert_eq!(
+                        typ,
+                        Type::Integer,
+                        "Expected the for loop {operand} to be an int but got '{typ}'"
+                    );
+                }
+                let old_stack = stack.clone();
+                let mut current_locals = visible_locals(&locals);
+                current_locals.insert(name.clone(), Type::Integer);
+                let body_exits = ExitStacks {
+                    loop_stack: Some(exits.whole_stack(&old_stack)),
+                    ..exits.clone()
+                };
+                if type_check_block(body.iter(), stack, current_locals, &body_exits) {
+                    *stack = old_stack;
+                } else {
+                    assert_eq!(
+                        &old_stack, stack,
+                        "The number of elements after the for body must be the same as before the for"
+                    );
+                }
+            }
+            Op::ForEach { name, body } => {
+                let collection = stack
+                    .pop()
+                    .expect("Expected a collection to iterate over but got nothing");
+                let element = match collection {
+                    Type::String => Type::Character,
+                    Type::Bytes => Type::U8,
+                    Type::List(element_type) => *element_type,
+                    _ => panic!("Cannot iterate over type '{collection}'"),
+                };
+                let old_stack = stack.clone();
+                let mut current_locals = visible_locals(&locals);
+                current_locals.insert(name.clone(), element);
+                let body_exits = ExitStacks {
+                    loop_stack: Some(exits.whole_stack(&old_stack)),
+                    ..exits.clone()
+                };
+                if type_check_block(body.iter(), stack, current_locals, &body_exits) {
+                    *stack = old_stack;
+                } else {
+                    assert_eq!(
+                        &old_stack, stack,
+                        "The number of elements after the for body must be the same as before the for"
+                    );
+                }
             }
             Op::Concat => {
                 let b = stack.pop().unwrap();
                 let a = stack.pop().unwrap();
                 stack.push(match (a, b) {
                     (Type::String, Type::String) => Type::String,
+                    (Type::Bytes, Type::Bytes) => Type::Bytes,
                     (a, b) => panic!("Cannot concat types '{a}' and '{b}'"),
                 });
             }
+            Op::BitAnd => {
+                let b = stack
+                    .pop()
+                    .expect("Expected first operand to band on the stack but got nothing");
+                let a = stack
+                    .pop()
+                    .expect("Expected second operand to band on the stack but got nothing");
+                stack.push(match (&a, &b) {
+                    (a, b) if a.is_integer() && a == b => a.clone(),
+                    _ => panic!("Cannot band types '{a}' and '{b}'"),
+                });
+            }
+            Op::BitOr => {
+                let b = stack
+                    .pop()
+                    .expect("Expected first operand to bor on the stack but got nothing");
+                let a = stack
+                    .pop()
+                    .expect("Expected second operand to bor on the stack but got nothing");
+                stack.push(match (&a, &b) {
+                    (a, b) if a.is_integer() && a == b => a.clone(),
+                    _ => panic!("Cannot bor types '{a}' and '{b}'"),
+                });
+            }
+            Op::BitXor => {
+                let b = stack
+                    .pop()
+                    .expect("Expected first operand to bxor on the stack but got nothing");
+                let a = stack
+                    .pop()
+                    .expect("Expected second operand to bxor on the stack but got nothing");
+                stack.push(match (&a, &b) {
+                    (a, b) if a.is_integer() && a == b => a.clone(),
+                    _ => panic!("Cannot bxor types '{a}' and '{b}'"),
+                });
+            }
+            Op::ShiftLeft => {
+                let amount = stack
+                    .pop()
+                    .expect("Expected shift amount on the stack but got nothing");
+                let value = stack
+                    .pop()
+                    .expect("Expected a value to shift left on the stack but got nothing");
+                stack.push(match (&value, &amount) {
+                    (value, Type::Integer) if value.is_integer() => value.clone(),
+                    _ => panic!("Cannot shift left type '{value}' by type '{amount}'"),
+                });
+            }
+            Op::ShiftRight => {
+                let amount = stack
+                    .pop()
+                    .expect("Expected shift amount on the stack but got nothing");
+                let value = stack
+                    .pop()
+                    .expect("Expected a value to shift right on the stack but got nothing");
+                stack.push(match (&value, &amount) {
+                    (value, Type::Integer) if value.is_integer() => value.clone(),
+                    _ => panic!("Cannot shift right type '{value}' by type '{amount}'"),
+                });
+            }
+            Op::Convert(to) => {
+                let from = stack
+                    .pop()
+                    .expect("Expected a value to convert on the stack but got nothing");
+                match (&from, to) {
+                    (from, to) if from.is_integer() && to.is_integer() => {}
+                    (Type::String, Type::Bytes) | (Type::Bytes, Type::String) => {}
+                    _ => panic!("Cannot convert type '{from}' to type '{to}'"),
+                }
+                stack.push(to.clone());
+            }
+            Op::BytesLength => {
+                let typ = stack
+                    .pop()
+                    .expect("Expected bytes to get the length of but got nothing");
+                assert_eq!(typ, Type::Bytes, "Expected bytes but got '{typ}'");
+                stack.push(Type::Integer);
+            }
+            Op::BytesGet => {
+                let index = stack
+                    .pop()
+                    .expect("Expected an index into the bytes but got nothing");
+                let typ = stack
+                    .pop()
+                    .expect("Expected bytes to index into but got nothing");
+                assert_eq!(typ, Type::Bytes, "Expected bytes but got '{typ}'");
+                assert_eq!(
+                    index,
+                    Type::Integer,
+                    "Expected the index to be an int but got '{index}'"
+                );
+                stack.push(Type::U8);
+            }
+            Op::BytesPush => {
+                let byte = stack
+                    .pop()
+                    .expect("Expected a byte to push but got nothing");
+                let typ = stack
+                    .pop()
+                    .expect("Expected bytes to push onto but got nothing");
+                assert_eq!(typ, Type::Bytes, "Expected bytes but got '{typ}'");
+                assert_eq!(byte, Type::U8, "Expected a u8 to push but got '{byte}'");
+                stack.push(Type::Bytes);
+            }
+            Op::ListLength => {
+                let typ = stack
+                    .pop()
+                    .expect("Expected a list to get the length of but got nothing");
+                assert!(
+                    matches!(typ, Type::List(_)),
+                    "Expected a list but got '{typ}'"
+                );
+                stack.push(Type::Integer);
+            }
+            Op::ListGet => {
+                let index = stack
+                    .pop()
+                    .expect("Expected an index into the list but got nothing");
+                let typ = stack
+                    .pop()
+                    .expect("Expected a list to index into but got nothing");
+                let Type::List(element_type) = typ else {
+                    panic!("Expected a list but got '{typ}'");
+                };
+                assert_eq!(
+                    index,
+                    Type::Integer,
+                    "Expected the index to be an int but got '{index}'"
+                );
+                stack.push(*element_type);
+            }
+            Op::ListPush => {
+                let value = stack
+                    .pop()
+                    .expect("Expected a value to push but got nothing");
+                let typ = stack
+                    .pop()
+                    .expect("Expected a list to push onto but got nothing");
+                let Type::List(element_type) = &typ else {
+                    panic!("Expected a list but got '{typ}'");
+                };
+                assert_eq!(
+                    **element_type, value,
+                    "Expected a value of type '{element_type}' to push but got '{value}'"
+                );
+                stack.push(typ);
+            }
+            Op::And => {
+                let b = stack
+                    .pop()
+                    .expect("Expected first operand to and on the stack but got nothing");
+                let a = stack
+                    .pop()
+                    .expect("Expected second operand to and on the stack but got nothing");
+                stack.push(match (a, b) {
+                    (Type::Boolean, Type::Boolean) => Type::Boolean,
+                    (a, b) => panic!("Cannot and types '{a}' and '{b}'"),
+                });
+            }
+            Op::Or => {
+                let b = stack
+                    .pop()
+                    .expect("Expected first operand to or on the stack but got nothing");
+                let a = stack
+                    .pop()
+                    .expect("Expected second operand to or on the stack but got nothing");
+                stack.push(match (a, b) {
+                    (Type::Boolean, Type::Boolean) => Type::Boolean,
+                    (a, b) => panic!("Cannot or types '{a}' and '{b}'"),
+                });
+            }
+            Op::Xor => {
+                let b = stack
+                    .pop()
+                    .expect("Expected first operand to xor on the stack but got nothing");
+                let a = stack
+                    .pop()
+                    .expect("Expected second operand to xor on the stack but got nothing");
+                stack.push(match (a, b) {
+                    (Type::Boolean, Type::Boolean) => Type::Boolean,
+                    (a, b) => panic!("Cannot xor types '{a}' and '{b}'"),
+                });
+            }
+            Op::AndThen(body) | Op::OrElse(body) => {
+                let word = match op {
+                    Op::AndThen(_) => "and_then",
+                    _ => "or_else",
+                };
+                let condition = stack
+                    .pop()
+                    .unwrap_or_else(|| panic!("Expected a boolean to {word} but got nothing"));
+                assert_eq!(
+                    condition,
+                    Type::Boolean,
+                    "Expected a boolean for {word} but got '{condition}'"
+                );
+                let current_locals = visible_locals(&locals);
+                let mut expected_stack = stack.clone();
+                expected_stack.push(Type::Boolean);
+                if type_check_block(body.iter(), stack, current_locals, exits) {
+                    *stack = expected_stack;
+                } else {
+                    assert_eq!(
+                        &expected_stack, stack,
+                        "The {word} body must leave the stack as it was with an extra boolean on top"
+                    );
+                }
+            }
             Op::Assert => {
                 let condition = stack
                     .pop()
@@ -379,7 +941,181 @@ pub fn type_check<'a>(
                     "Expected a boolean for the assert condition but got '{condition}'"
                 );
             }
+            Op::Break => {
+                let loop_stack = exits
+                    .loop_stack
+                    .as_ref()
+                    .expect("Cannot break outside of a loop");
+                assert_eq!(
+                    loop_stack,
+                    &exits.whole_stack(stack),
+                    "The types on the stack at a break must be the same as before the loop"
+                );
+                exited_by = Some("break");
+            }
+            Op::Continue => {
+                let loop_stack = exits
+                    .loop_stack
+                    .as_ref()
+                    .expect("Cannot continue outside of a loop");
+                assert_eq!(
+                    loop_stack,
+                    &exits.whole_stack(stack),
+                    "The types on the stack at a continue must be the same as before the loop"
+                );
+                exited_by = Some("continue");
+            }
+            Op::Throw => {
+                stack
+                    .pop()
+                    .expect("Expected a value to throw but got nothing");
+                exited_by = Some("throw");
+            }
+            Op::Try {
+                body,
+                catch_type,
+                handler,
+            } => {
+                let current_locals = visible_locals(&locals);
+                let mut handler_stack = stack.clone();
+                handler_stack.push(catch_type.clone());
+                // the body starts from an empty stack, values it took from under the try would
+                // be gone when the handler runs
+                let try_exits = ExitStacks {
+                    hidden: exits.whole_stack(stack),
+                    ..exits.clone()
+                };
+                let mut body_stack = vec![];
+                let body_exits = type_check_block(
+                    body.iter(),
+                    &mut body_stack,
+                    current_locals.clone(),
+                    &try_exits,
+                );
+                stack.append(&mut body_stack);
+                let handler_exits =
+                    type_check_block(handler.iter(), &mut handler_stack, current_locals, exits);
+                match (body_exits, handler_exits) {
+                    (true, true) => exited_by = Some("try"),
+                    (true, false) => *stack = handler_stack,
+                    (false, true) => {}
+                    (false, false) => assert_eq!(
+                        &handler_stack, stack,
+                        "Both the try and the catch must result in the same types on the stack"
+                    ),
+                }
+            }
+            Op::Return => {
+                let return_types = exits
+                    .return_types
+                    .as_ref()
+                    .expect("Cannot return outside of a procedure");
+                assert_eq!(
+                    return_types,
+                    &exits.whole_stack(stack),
+                    "The types on the stack at a return must be the procedure's return types"
+                );
+                exited_by = Some("return");
+            }
+            Op::MakeCoroutine => {
+                let procedure = stack
+                    .pop()
+                    .expect("Expected a procedure to make a coroutine from but got nothing");
+                match procedure {
+                    Type::Procedure {
+                        arguments,
+                        return_values,
+                    } => stack.push(Type::Coroutine {
+                        resume_values: arguments,
+                        yield_values: return_values,
+                    }),
+                    _ => panic!(
+                        "Expected a procedure to make a coroutine from but got type '{procedure}'"
+                    ),
+                }
+            }
+            Op::Resume => {
+                let coroutine = stack
+                    .pop()
+                    .expect("Expected a coroutine to resume but got nothing");
+                let (resume_values, yield_values) = match &coroutine {
+                    Type::Coroutine {
+                        resume_values,
+                        yield_values,
+                    } => (resume_values, yield_values),
+                    _ => panic!("Expected a coroutine to resume but got type '{coroutine}'"),
+                };
+                for (i, typ) in resume_values.iter().enumerate().rev() {
+                    let actual_typ = stack.pop().unwrap_or_else(|| {
+                        panic!("Expected resume value {i} on the stack but got nothing")
+                    });
+                    assert_eq!(
+                        typ, &actual_typ,
+                        "Expected resume value {i} to be type '{typ}' but got '{actual_typ}'"
+                    );
+                }
+                stack.extend(yield_values.iter().cloned());
+                stack.push(Type::Boolean);
+            }
+            // whether the procedure will run as a coroutine isn't known here, so a `yield` from a
+            // plain call is only caught when it runs
+            Op::Yield => {
+                let (Some(return_types), Some(resume_types)) =
+                    (&exits.return_types, &exits.resume_types)
+                else {
+                    panic!("Cannot yield outside of a procedure");
+                };
+                let depth = return_types.len();
+                expect_depth(stack, depth, "yield");
+                assert_eq!(
+                    return_types,
+                    &stack[stack.len() - depth..],
+                    "The types yielded must be the procedure's return types"
+                );
+                stack.truncate(stack.len() - depth);
+                stack.extend(resume_types.iter().cloned());
+            }
         }
     }
     assert_eq!(locals.len(), 1);
+    exited_by.is_some()
+}
+
+/// The locals of every scope, with inner ones shadowing outer ones of the same name
+fn visible_locals(locals: &[HashMap<String, Type>]) -> HashMap<String, Type> {
+    let mut visible = HashMap::new();
+    for (name, local) in locals.iter().rev().flatten() {
+        if !visible.contains_key(name) {
+            visible.insert(name.clone(), local.clone());
+        }
+    }
+    visible
+}
+
+fn expect_depth(stack: &[Type], depth: usize, word: &str) {
+    assert!(
+        stack.len() >= depth,
+        "Expected at least {depth} elements to {word} but the stack only has {} elements",
+        stack.len()
+    );
+}
+
+fn call_procedure_type(stack: &mut Vec<Type>, procedure_type: &Type) {
+    let (arguments, return_values) = match procedure_type {
+        Type::Procedure {
+            arguments,
+            return_values,
+        } => (arguments, return_values),
+        _ => panic!("Expected a procedure to call but got type '{procedure_type}'"),
+    };
+    for (i, typ) in arguments.iter().enumerate().rev() {
+        let actual_typ = stack
+            .pop()
+            .unwrap_or_else(|| panic!("Expected argument {i} on the stack but got nothing"));
+        assert_eq!(
+            typ, &actual_typ,
+            "Expected argument {i} to be type '{typ}' but got '{actual_typ}'"
+        );
+    }
+    stack.append(&mut return_values.clone());
 }
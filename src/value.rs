@@ -1,6 +1,13 @@
-use std::{cell::Cell, collections::HashMap, rc::Rc};
+use std::{
+    any::Any,
+    cell::{Cell, RefCell},
+    cmp::Ordering,
+    rc::Rc,
+};
 
-use crate::{Op, Type};
+use crate::{Chunk, Coroutine, OpaqueType, Type};
+
+pub type NativeFunction = Rc<dyn Fn(&mut Vec<Value>) -> Result<(), Value>>;
 
 #[derive(Clone)]
 pub enum Value {
@@ -11,13 +18,22 @@ pub enum Value {
     Boolean(bool),
     Character(char),
     Integer(i64),
+    U8(u8),
+    I32(i32),
+    U64(u64),
+    Bytes(Vec<u8>),
+    /// The elements along with their type, which an empty list still needs
+    List(Type, Vec<Value>),
     Function {
         typ: Type,
-        ops: Rc<Vec<Op>>,
-        locals: HashMap<String, Rc<Cell<Value>>>,
+        chunk: Rc<Chunk>,
+        captures: Vec<Rc<Cell<Value>>>,
     },
-    BuiltinFunction(Type, Rc<dyn Fn(&mut Vec<Value>)>),
+    BuiltinFunction(Type, NativeFunction),
     Reference(Rc<Cell<Value>>),
+    Coroutine(Type, Rc<RefCell<Coroutine>>),
+    /// An object from the host, of the opaque type with the name
+    Opaque(String, Rc<dyn Any>),
 }
 
 impl PartialEq for Value {
@@ -30,25 +46,47 @@ impl PartialEq for Value {
             (Value::Boolean(a), Value::Boolean(b)) => a == b,
             (Value::Character(a), Value::Character(b)) => a == b,
             (Value::Integer(a), Value::Integer(b)) => a == b,
+            (Value::U8(a), Value::U8(b)) => a == b,
+            (Value::I32(a), Value::I32(b)) => a == b,
+            (Value::U64(a), Value::U64(b)) => a == b,
+            (Value::Bytes(a), Value::Bytes(b)) => a == b,
+            (Value::List(a_typ, a), Value::List(b_typ, b)) => a_typ == b_typ && a == b,
             (
                 Value::Function {
                     typ: a_typ,
-                    ops: a_ops,
+                    chunk: a_chunk,
                     ..
                 },
                 Value::Function {
                     typ: b_typ,
-                    ops: b_ops,
+                    chunk: b_chunk,
                     ..
                 },
-            ) => a_typ == b_typ && a_ops.as_ptr() == b_ops.as_ptr(),
+            ) => a_typ == b_typ && Rc::ptr_eq(a_chunk, b_chunk),
             (Value::BuiltinFunction(_, _), Value::BuiltinFunction(_, _)) => false, // TODO: find a way to compare builtin functions
             (Value::Reference(a), Value::Reference(b)) => a.as_ptr() == b.as_ptr(),
+            (Value::Coroutine(_, a), Value::Coroutine(_, b)) => Rc::ptr_eq(a, b),
+            (Value::Opaque(_, a), Value::Opaque(_, b)) => Rc::ptr_eq(a, b),
             _ => false,
         }
     }
 }
 
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Value::String(a), Value::String(b)) => a.partial_cmp(b),
+            (Value::Boolean(a), Value::Boolean(b)) => a.partial_cmp(b),
+            (Value::Character(a), Value::Character(b)) => a.partial_cmp(b),
+            (Value::Integer(a), Value::Integer(b)) => a.partial_cmp(b),
+            (Value::U8(a), Value::U8(b)) => a.partial_cmp(b),
+            (Value::I32(a), Value::I32(b)) => a.partial_cmp(b),
+            (Value::U64(a), Value::U64(b)) => a.partial_cmp(b),
+            _ => None,
+        }
+    }
+}
+
 impl Value {
     pub fn get_type(&self) -> Type {
         match self {
@@ -59,6 +97,11 @@ impl Value {
             Value::Boolean(_) => Type::Boolean,
             Value::Character(_) => Type::Character,
             Value::Integer(_) => Type::Integer,
+            Value::U8(_) => Type::U8,
+            Value::I32(_) => Type::I32,
+            Value::U64(_) => Type::U64,
+            Value::Bytes(_) => Type::Bytes,
+            Value::List(element_type, _) => Type::List(Box::new(element_type.clone())),
             Value::Function { typ, .. } => typ.clone(),
             Value::BuiltinFunction(typ, _) => typ.clone(),
             Value::Reference(reference) => {
@@ -67,6 +110,29 @@ impl Value {
                 reference.set(value);
                 Type::Reference(Box::new(clone.get_type()))
             }
+            Value::Coroutine(typ, _) => typ.clone(),
+            Value::Opaque(name, _) => Type::Opaque(name.clone()),
+        }
+    }
+
+    /// Wraps an object from the host so programs can pass it around as its opaque type
+    pub fn opaque<T: OpaqueType>(object: T) -> Value {
+        Value::Opaque(T::NAME.to_string(), Rc::new(object))
+    }
+
+    /// The object from the host if this is an opaque value holding a `T`
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        match self {
+            Value::Opaque(_, object) => object.downcast_ref(),
+            _ => None,
+        }
+    }
+
+    /// Shares the object from the host if this is an opaque value holding a `T`
+    pub fn downcast_rc<T: Any>(&self) -> Option<Rc<T>> {
+        match self {
+            Value::Opaque(_, object) => object.clone().downcast().ok(),
+            _ => None,
         }
     }
 }
@@ -81,6 +147,20 @@ impl std::fmt::Display for Value {
             Value::Boolean(value) => write!(f, "{value}"),
             Value::Character(value) => write!(f, "{value:?}"),
             Value::Integer(value) => write!(f, "{value}"),
+            Value::U8(value) => write!(f, "{value}u8"),
+            Value::I32(value) => write!(f, "{value}i32"),
+            Value::U64(value) => write!(f, "{value}u64"),
+            Value::Bytes(value) => write!(f, "{value:?}"),
+            Value::List(_, values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                write!(f, "]")
+            }
             Value::Function { typ, .. } => write!(f, "{typ}"),
             Value::BuiltinFunction(typ, _) => write!(f, "{typ}"),
             Value::Reference(reference) => {
@@ -89,6 +169,15 @@ impl std::fmt::Display for Value {
                 reference.set(value);
                 write!(f, "{clone}")
             }
+            Value::Coroutine(typ, _) => write!(f, "{typ}"),
+            Value::Opaque(name, _) => write!(f, "{name}"),
         }
     }
 }
+
+/// The same as `Display`, procedures and host objects can't show more than their type
+impl std::fmt::Debug for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{self}")
+    }
+}
@@ -0,0 +1,333 @@
+use std::{cell::Cell, collections::HashMap, path::Path, rc::Rc};
+
+use crate::{
+    call_procedure_with_limits, compile_ops_on_stack, lower, new_cell,
+    stdlib::{self, Module},
+    Chunk, Execution, FromValue, Interrupt, Limits, NativeProcedure, OpaqueType, RuntimeError,
+    Type, Value,
+};
+
+/// An interpreter that keeps its globals and stack from one program to the next
+pub struct Vm {
+    constants: HashMap<String, Vec<Value>>,
+    globals: HashMap<String, Global>,
+    stack: Vec<Value>,
+    limits: Limits,
+    interrupt: Interrupt,
+}
+
+/// A variable shared by the host and every program, it keeps the type it was declared with
+struct Global {
+    typ: Type,
+    cell: Rc<Cell<Value>>,
+}
+
+impl Global {
+    fn new(value: Value) -> Global {
+        Global {
+            typ: value.get_type(),
+            cell: new_cell(value),
+        }
+    }
+
+    fn get(&self) -> Value {
+        let value = self.cell.replace(Value::Null);
+        self.cell.set(value.clone());
+        value
+    }
+}
+
+/// Why the host couldn't set a global
+#[derive(Debug)]
+pub enum GlobalError {
+    Undeclared(String),
+    WrongType { expected: Type, found: Type },
+}
+
+impl std::fmt::Display for GlobalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            GlobalError::Undeclared(name) => write!(f, "There is no global named '{name}'"),
+            GlobalError::WrongType { expected, found } => {
+                write!(f, "Expected a value of type {expected} but got {found}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for GlobalError {}
+
+/// Why `Vm::run_file` failed
+#[derive(Debug)]
+pub enum RunFileError {
+    Read(std::io::Error),
+    Runtime(RuntimeError),
+}
+
+impl std::fmt::Display for RunFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RunFileError::Read(error) => write!(f, "Unable to read file: {error}"),
+            RunFileError::Runtime(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl std::error::Error for RunFileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RunFileError::Read(error) => Some(error),
+            RunFileError::Runtime(error) => Some(error),
+        }
+    }
+}
+
+/// Sets up the builtins, constants and globals every program run by a `Vm` can use
+#[derive(Default)]
+pub struct VmBuilder {
+    constants: HashMap<String, Vec<Value>>,
+    globals: HashMap<String, Value>,
+    limits: Limits,
+}
+
+impl VmBuilder {
+    /// Adds a constant that pushes a native procedure of the given type
+    pub fn builtin(
+        self,
+        name: &str,
+        typ: Type,
+        function: impl Fn(&mut Vec<Value>) -> Result<(), Value> + 'static,
+    ) -> Self {
+        self.constant(name, vec![Value::BuiltinFunction(typ, Rc::new(function))])
+    }
+
+    /// Adds a constant that pushes a Rust function as a procedure, typed from its signature
+    pub fn register<Arguments>(
+        self,
+        name: &str,
+        function: impl NativeProcedure<Arguments>,
+    ) -> Self {
+        self.constant(name, vec![function.into_value()])
+    }
+
+    /// Adds a constant with the name of the host type that pushes its opaque type, so programs
+    /// can write it in their own types
+    pub fn opaque_type<T: OpaqueType>(self) -> Self {
+        self.constant(T::NAME, vec![Value::Type(T::opaque_type())])
+    }
+
+    /// Adds the builtins of a module from `stdlib`
+    pub fn module(self, module: Module) -> Self {
+        module(self)
+    }
+
+    /// Adds every module from `stdlib`
+    pub fn stdlib(self) -> Self {
+        stdlib::ALL.into_iter().fold(self, VmBuilder::module)
+    }
+
+    /// Adds a constant that pushes the values in order
+    pub fn constant(mut self, name: &str, values: Vec<Value>) -> Self {
+        self.constants.insert(name.to_string(), values);
+        self
+    }
+
+    /// Adds a variable that can be read and stored to by every program, it only ever holds
+    /// values of the same type as `value`
+    pub fn global(mut self, name: &str, value: Value) -> Self {
+        self.globals.insert(name.to_string(), value);
+        self
+    }
+
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn build(self) -> Vm {
+        Vm {
+            constants: self.constants,
+            globals: self
+                .globals
+                .into_iter()
+                .map(|(name, value)| (name, Global::new(value)))
+                .collect(),
+            stack: vec![],
+            limits: self.limits,
+            interrupt: Interrupt::default(),
+        }
+    }
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Vm::builder().build()
+    }
+}
+
+impl Vm {
+    pub fn builder() -> VmBuilder {
+        VmBuilder::default()
+    }
+
+    /// Compiles a program against the globals and the values on the stack
+    ///
+    /// # Panics
+    ///
+    /// If the program doesn't compile, the same as `compile_ops`
+    pub fn compile(&self, source: &str) -> Chunk {
+        let stack = self.stack.iter().map(Value::get_type).collect::<Vec<_>>();
+        let ops =
+            compile_ops_on_stack(source, &self.global_cells(), self.constants.clone(), &stack);
+        lower(&ops, &self.globals.keys().cloned().collect::<Vec<_>>())
+    }
+
+    /// Runs a program on top of whatever earlier programs left on the stack
+    ///
+    /// # Panics
+    ///
+    /// If the program doesn't compile, the same as `compile_ops`
+    pub fn eval(&mut self, source: &str) -> Result<(), RuntimeError> {
+        let mut execution = self.start(source);
+        let result = execution.run();
+        self.finish(execution);
+        result
+    }
+
+    /// Compiles a program to be run a few instructions at a time, it takes the stack with it until
+    /// it is handed back to `finish`
+    ///
+    /// # Panics
+    ///
+    /// If the program doesn't compile, the same as `compile_ops`
+    pub fn start(&mut self, source: &str) -> Execution {
+        let chunk = Rc::new(self.compile(source));
+        Execution::new(
+            &chunk,
+            std::mem::take(&mut self.stack),
+            self.global_cells(),
+            self.limits.clone(),
+        )
+        .with_interrupt(self.interrupt.clone())
+    }
+
+    /// Takes back the stack and the fuel that is left from a program made by `start`, whether it
+    /// finished or not
+    pub fn finish(&mut self, execution: Execution) {
+        self.limits = execution.limits().clone();
+        self.stack = execution.into_stack();
+    }
+
+    /// # Panics
+    ///
+    /// If the file doesn't compile
+    pub fn run_file(&mut self, path: impl AsRef<Path>) -> Result<(), RunFileError> {
+        let source = std::fs::read_to_string(path).map_err(RunFileError::Read)?;
+        self.eval(&source).map_err(RunFileError::Runtime)
+    }
+
+    /// Calls a procedure, such as one a program left on the stack, under the limits and interrupt
+    /// of the `Vm`
+    pub fn call_procedure(
+        &mut self,
+        procedure: &Value,
+        arguments: Vec<Value>,
+    ) -> Result<Vec<Value>, RuntimeError> {
+        call_procedure_with_limits(procedure, arguments, &mut self.limits, &self.interrupt)
+    }
+
+    /// Adds a constant that pushes a Rust function as a procedure to the programs run from now on
+    pub fn register<Arguments>(&mut self, name: &str, function: impl NativeProcedure<Arguments>) {
+        self.constants
+            .insert(name.to_string(), vec![function.into_value()]);
+    }
+
+    pub fn stack(&self) -> &[Value] {
+        &self.stack
+    }
+
+    pub fn push(&mut self, value: Value) {
+        self.stack.push(value);
+    }
+
+    pub fn pop(&mut self) -> Option<Value> {
+        self.stack.pop()
+    }
+
+    pub fn clear_stack(&mut self) {
+        self.stack.clear();
+    }
+
+    /// Adds a global for the programs run from now on, returning the cell it is kept in
+    ///
+    /// # Panics
+    ///
+    /// If there already is a global with the name
+    pub fn declare_global(&mut self, name: &str, value: Value) -> Rc<Cell<Value>> {
+        let global = Global::new(value);
+        let cell = global.cell.clone();
+        assert!(
+            self.globals.insert(name.to_string(), global).is_none(),
+            "Redeclaration of global '{name}'"
+        );
+        cell
+    }
+
+    /// The current value of a global
+    pub fn global(&self, name: &str) -> Option<Value> {
+        self.globals.get(name).map(Global::get)
+    }
+
+    /// The current value of a global, if it is of the Rust type `T`
+    pub fn global_as<T: FromValue>(&self, name: &str) -> Option<T> {
+        self.global(name).and_then(T::from_value)
+    }
+
+    /// Changes a global, as long as the value is of the type it was declared with
+    pub fn set_global(&self, name: &str, value: Value) -> Result<(), GlobalError> {
+        let global = self
+            .globals
+            .get(name)
+            .ok_or_else(|| GlobalError::Undeclared(name.to_string()))?;
+        let found = value.get_type();
+        if found != global.typ {
+            return Err(GlobalError::WrongType {
+                expected: global.typ.clone(),
+                found,
+            });
+        }
+        global.cell.set(value);
+        Ok(())
+    }
+
+    /// The cell a global is kept in, shared with the programs that use it
+    ///
+    /// Nothing stops the host from putting a value of another type in it, which programs that
+    /// were compiled for the old type won't expect.
+    pub fn global_cell(&self, name: &str) -> Option<Rc<Cell<Value>>> {
+        self.globals.get(name).map(|global| global.cell.clone())
+    }
+
+    fn global_cells(&self) -> HashMap<String, Rc<Cell<Value>>> {
+        self.globals
+            .iter()
+            .map(|(name, global)| (name.clone(), global.cell.clone()))
+            .collect()
+    }
+
+    /// The limits programs run under, with the fuel they have left between them
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Changes the limits for the programs run from now on, such as to give them more fuel
+    pub fn limits_mut(&mut self) -> &mut Limits {
+        &mut self.limits
+    }
+
+    /// A handle that stops whichever program the `Vm` is running, and every later one until it is
+    /// reset
+    pub fn interrupt_handle(&self) -> Interrupt {
+        self.interrupt.clone()
+    }
+}
@@ -0,0 +1,54 @@
+use stack_lang::*;
+
+fn run(source: &str) -> Vec<Value> {
+    let mut vm = Vm::default();
+    vm.eval(source).unwrap();
+    vm.stack().to_vec()
+}
+
+#[test]
+fn switch_without_else_does_nothing_when_no_case_matches() {
+    let stack = run("
+        const(:pick_number proc(label) -> (int) {
+            0 var(:n)
+            switch {
+                case(:red) { 1 get(:n) store }
+                case(:green) { 2 get(:n) store }
+            }
+            get(:n) load
+        })
+        :green pick_number call
+        :blue pick_number call
+    ");
+    assert_eq!(stack, [Value::Integer(2), Value::Integer(0)]);
+}
+
+#[test]
+#[should_panic(expected = "Every case of a switch must result in the same types on the stack")]
+fn switch_without_else_whose_case_pushes_a_value() {
+    run(":red switch { case(:red) { 1 } }");
+}
+
+#[test]
+#[should_panic(expected = "Every case of a switch must result in the same types on the stack")]
+fn switch_without_else_whose_case_drops_a_value() {
+    run("1 :red switch { case(:red) { drop } }");
+}
+
+#[test]
+#[should_panic(expected = "Every arm of a cond must result in the same types on the stack")]
+fn cond_without_else_whose_arm_pushes_a_value() {
+    run("cond { true { 1 } }");
+}
+
+#[test]
+#[should_panic(expected = "Expected switch case value to be type 'label' but got 'int'")]
+fn switch_case_of_the_wrong_type() {
+    run(":red switch { case(1) { } }");
+}
+
+#[test]
+#[should_panic(expected = "A case can only be used directly inside a switch")]
+fn case_outside_a_switch() {
+    run("case(1) { }");
+}
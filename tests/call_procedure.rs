@@ -0,0 +1,87 @@
+use std::{thread, time::Duration};
+
+use stack_lang::*;
+
+fn add_procedure(vm: &mut Vm) -> Value {
+    vm.eval("proc(int int) -> (int) { add }").unwrap();
+    vm.pop().unwrap()
+}
+
+#[test]
+fn procedure_left_by_a_program_can_be_called() {
+    let mut vm = Vm::default();
+    let add = add_procedure(&mut vm);
+    let results = vm
+        .call_procedure(&add, vec![Value::Integer(2), Value::Integer(3)])
+        .unwrap();
+    assert_eq!(results, [Value::Integer(5)]);
+}
+
+#[test]
+fn builtin_can_be_called() {
+    let mut vm = Vm::builder().stdlib().build();
+    vm.eval("string_length").unwrap();
+    let length = vm.pop().unwrap();
+    let results = vm
+        .call_procedure(&length, vec![Value::String("héllo".into())])
+        .unwrap();
+    assert_eq!(results, [Value::Integer(5)]);
+}
+
+#[test]
+fn value_that_is_not_a_procedure_is_rejected() {
+    let mut vm = Vm::default();
+    let result = vm.call_procedure(&Value::Integer(1), vec![]);
+    assert!(matches!(
+        result,
+        Err(RuntimeError::NotAProcedure(Type::Integer))
+    ));
+}
+
+#[test]
+fn arguments_of_the_wrong_type_are_rejected() {
+    let mut vm = Vm::default();
+    let add = add_procedure(&mut vm);
+    let result = vm.call_procedure(&add, vec![Value::Integer(2), Value::Boolean(true)]);
+    match result {
+        Err(RuntimeError::WrongArguments { expected, found }) => {
+            assert_eq!(expected, [Type::Integer, Type::Integer]);
+            assert_eq!(found, [Type::Integer, Type::Boolean]);
+        }
+        result => panic!("Expected the arguments to be rejected but got {result:?}"),
+    }
+}
+
+#[test]
+fn wrong_number_of_arguments_is_rejected() {
+    let mut vm = Vm::default();
+    let add = add_procedure(&mut vm);
+    let result = vm.call_procedure(&add, vec![Value::Integer(2)]);
+    assert!(matches!(result, Err(RuntimeError::WrongArguments { .. })));
+}
+
+#[test]
+fn thrown_value_is_returned_as_an_error() {
+    let mut vm = Vm::default();
+    vm.eval(r#"proc() -> () { "no" throw }"#).unwrap();
+    let procedure = vm.pop().unwrap();
+    match vm.call_procedure(&procedure, vec![]) {
+        Err(RuntimeError::Thrown(value)) => assert_eq!(value, Value::String("no".into())),
+        result => panic!("Expected a string to be thrown but got {result:?}"),
+    }
+}
+
+#[test]
+fn callback_that_never_returns_can_be_interrupted() {
+    let mut vm = Vm::default();
+    vm.eval("proc() -> () { while true { } }").unwrap();
+    let callback = vm.pop().unwrap();
+    let interrupt = vm.interrupt_handle();
+    let interrupter = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(10));
+        interrupt.interrupt();
+    });
+    let result = vm.call_procedure(&callback, vec![]);
+    assert!(matches!(result, Err(RuntimeError::Interrupted)));
+    interrupter.join().unwrap();
+}
@@ -0,0 +1,69 @@
+use stack_lang::*;
+
+fn run(source: &str) -> Vec<Value> {
+    let mut vm = Vm::builder().stdlib().build();
+    vm.eval(source).unwrap();
+    vm.stack().to_vec()
+}
+
+#[test]
+fn chars_to_and_from_code_points() {
+    let stack = run("
+        97 char_from_code call
+        233 char_from_code call char_to_code call
+    ");
+    assert_eq!(stack, [Value::Character('a'), Value::Integer(233)]);
+}
+
+#[test]
+fn code_that_is_not_a_char_throws() {
+    let mut vm = Vm::builder().stdlib().build();
+    // a surrogate, which only exists in UTF-16
+    match vm.eval("55296 char_from_code call") {
+        Err(RuntimeError::Thrown(Value::String(message))) => {
+            assert_eq!(message, "55296 is not the code point of a char")
+        }
+        result => panic!("Expected a string to be thrown but got {result:?}"),
+    }
+}
+
+#[test]
+fn char_classes() {
+    let stack = run(r#"
+        "7" 0 string_get call char_is_digit call
+        "x" 0 string_get call char_is_digit call
+        "é" 0 string_get call char_is_alphabetic call
+        "1" 0 string_get call char_is_alphabetic call
+        " " 0 string_get call char_is_whitespace call
+        "x" 0 string_get call char_is_whitespace call
+    "#);
+    let expected = [true, false, true, false, true, false].map(Value::Boolean);
+    assert_eq!(stack, expected);
+}
+
+#[test]
+fn changing_case_keeps_chars_that_would_become_several() {
+    let stack = run(r#"
+        "é" 0 string_get call char_upper call
+        "Q" 0 string_get call char_lower call
+        "ß" 0 string_get call char_upper call
+    "#);
+    assert_eq!(
+        stack,
+        [
+            Value::Character('É'),
+            Value::Character('q'),
+            Value::Character('ß')
+        ]
+    );
+}
+
+#[test]
+fn strings_are_built_and_taken_apart_a_char_at_a_time() {
+    let stack = run(r#"
+        "" "héllo" string_chars call for_each(:c) {
+            get(:c) load char_upper call string_push call
+        }
+    "#);
+    assert_eq!(stack, [Value::String("HÉLLO".into())]);
+}
@@ -0,0 +1,59 @@
+use stack_lang::*;
+
+fn run(source: &str) -> Vec<Value> {
+    let mut vm = Vm::builder().stdlib().build();
+    vm.eval(source).unwrap();
+    vm.stack().to_vec()
+}
+
+#[test]
+fn coroutine_yields_until_it_returns() {
+    let stack = run("
+        proc(int) -> (int) {
+            while dup 0 greater { dup yield drop 1 sub }
+        } coroutine var(:counter)
+        2 get(:counter) load resume
+        0 get(:counter) load resume
+        0 get(:counter) load resume
+    ");
+    assert_eq!(
+        stack,
+        [
+            Value::Integer(2),
+            Value::Boolean(true),
+            Value::Integer(1),
+            Value::Boolean(true),
+            Value::Integer(0),
+            Value::Boolean(false),
+        ]
+    );
+}
+
+#[test]
+fn resuming_a_finished_coroutine_throws() {
+    let stack = run(r#"
+        proc() -> () { } coroutine var(:done)
+        get(:done) load resume drop
+        try { get(:done) load resume drop "running" } catch(string) { drop "finished" }
+    "#);
+    assert_eq!(stack, [Value::String("finished".into())]);
+}
+
+#[test]
+fn yield_from_a_plain_call_throws() {
+    let stack = run(r#"
+        proc() -> (int) { 1 yield 2 } var(:generator)
+        try { get(:generator) load call } catch(string) { drop 0 }
+    "#);
+    assert_eq!(stack, [Value::Integer(0)]);
+}
+
+#[test]
+fn yield_from_a_procedure_called_by_a_coroutine_throws() {
+    let stack = run(r#"
+        proc() -> (int) { 1 yield 2 } var(:helper)
+        proc() -> (int) { get(:helper) load call } coroutine var(:outer)
+        try { get(:outer) load resume drop } catch(string) { drop 0 }
+    "#);
+    assert_eq!(stack, [Value::Integer(0)]);
+}
@@ -0,0 +1,40 @@
+use stack_lang::*;
+
+fn run(source: &str) -> Vec<Value> {
+    let mut vm = Vm::builder().stdlib().build();
+    vm.eval(source).unwrap();
+    vm.stack().to_vec()
+}
+
+#[test]
+fn handler_starts_from_the_stack_before_the_try() {
+    let stack = run(r#"1 2 try { 3 "oops" throw } catch(string) { drop 4 }"#);
+    assert_eq!(
+        stack,
+        [Value::Integer(1), Value::Integer(2), Value::Integer(4)]
+    );
+}
+
+#[test]
+#[should_panic(expected = "Expected at least 1 element to drop")]
+fn try_body_cannot_take_values_from_under_the_try() {
+    run(r#"1 2 try { drop drop "x" throw } catch(string) { drop } add"#);
+}
+
+#[test]
+fn break_and_return_can_leave_from_inside_a_try() {
+    let stack = run(r#"
+        0 while true { try { break } catch(string) { drop } }
+        proc() -> (int) { 5 try { return } catch(string) { drop } drop 6 } call
+    "#);
+    assert_eq!(stack, [Value::Integer(0), Value::Integer(5)]);
+}
+
+#[test]
+fn uncaught_throw_is_an_error() {
+    let mut vm = Vm::default();
+    match vm.eval("try { 1 throw } catch(string) { drop }") {
+        Err(RuntimeError::Thrown(value)) => assert_eq!(value, Value::Integer(1)),
+        result => panic!("Expected the int to be thrown but got {result:?}"),
+    }
+}
@@ -0,0 +1,63 @@
+use std::{collections::HashMap, rc::Rc, thread, time::Duration};
+
+use stack_lang::*;
+
+const PROGRAM: &str = "
+    proc(int) -> (int) {
+        while dup 0 greater { dup yield drop 1 sub }
+    } coroutine var(:counter)
+    0 var(:total)
+    5 while get(:counter) load resume {
+        get(:total) load add get(:total) store
+        0
+    }
+    drop get(:total) load
+";
+
+fn execution(source: &str) -> Execution {
+    let ops = compile_ops(source, &HashMap::new(), HashMap::new());
+    let chunk = Rc::new(lower(&ops, &[]));
+    Execution::new(&chunk, vec![], HashMap::new(), Limits::default())
+}
+
+#[test]
+fn running_a_few_steps_at_a_time_gives_the_same_result() {
+    let mut whole = execution(PROGRAM);
+    whole.run().unwrap();
+    assert_eq!(whole.stack(), [Value::Integer(15)]);
+
+    for steps in [1, 7, 100] {
+        let mut stepped = execution(PROGRAM);
+        let mut pauses = 0;
+        while stepped.run_steps(steps).unwrap() == Progress::Paused {
+            pauses += 1;
+        }
+        assert!(pauses > 0);
+        assert!(stepped.is_finished());
+        assert_eq!(stepped.stack(), whole.stack());
+    }
+}
+
+#[test]
+fn interrupt_stops_the_program_for_good() {
+    let mut execution = execution("while true { }");
+    assert_eq!(execution.run_steps(10).unwrap(), Progress::Paused);
+    execution.interrupt_handle().interrupt();
+    assert!(matches!(
+        execution.run_steps(10),
+        Err(RuntimeError::Interrupted)
+    ));
+    assert!(execution.is_finished());
+}
+
+#[test]
+fn interrupt_from_another_thread() {
+    let mut execution = execution("while true { }");
+    let interrupt = execution.interrupt_handle();
+    let interrupter = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(10));
+        interrupt.interrupt();
+    });
+    assert!(matches!(execution.run(), Err(RuntimeError::Interrupted)));
+    interrupter.join().unwrap();
+}
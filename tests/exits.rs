@@ -0,0 +1,70 @@
+use stack_lang::*;
+
+fn run(source: &str) -> Vec<Value> {
+    let mut vm = Vm::default();
+    vm.eval(source).unwrap();
+    vm.stack().to_vec()
+}
+
+#[test]
+fn break_and_return_leave_with_the_stack_they_expect() {
+    let stack = run("
+        0 while true { 1 add dup 5 equal if { break } }
+        4 proc(int) -> (int) { dup 3 greater if { return } 10 add } call
+    ");
+    assert_eq!(stack, [Value::Integer(5), Value::Integer(4)]);
+}
+
+#[test]
+#[should_panic(expected = "The types on the stack at a break must be the same as before the loop")]
+fn break_with_an_extra_value() {
+    run("0 while true { 1 break }");
+}
+
+#[test]
+#[should_panic(expected = "The types on the stack at a break must be the same as before the loop")]
+fn break_with_a_value_missing() {
+    run("0 while true { drop break }");
+}
+
+#[test]
+#[should_panic(
+    expected = "The types on the stack at a continue must be the same as before the loop"
+)]
+fn continue_with_a_different_type() {
+    run(r#"0 while true { drop "zero" continue }"#);
+}
+
+#[test]
+#[should_panic(
+    expected = "The types on the stack at a return must be the procedure's return types"
+)]
+fn return_with_the_wrong_types() {
+    run(r#"proc(int) -> (int) { drop "one" return }"#);
+}
+
+#[test]
+#[should_panic(
+    expected = "The types on the stack at a return must be the procedure's return types"
+)]
+fn return_with_an_extra_value() {
+    run("proc(int) -> (int) { 2 return }");
+}
+
+#[test]
+#[should_panic(expected = "Unreachable code after 'break'")]
+fn code_after_break() {
+    run("0 while true { break 1 add }");
+}
+
+#[test]
+#[should_panic(expected = "Unreachable code after 'return'")]
+fn code_after_return() {
+    run("proc(int) -> (int) { return 1 add }");
+}
+
+#[test]
+#[should_panic(expected = "Cannot break outside of a loop")]
+fn break_outside_a_loop() {
+    run("break");
+}
@@ -0,0 +1,42 @@
+use stack_lang::*;
+
+/// Runs the program and checks that the objects it leaves behind are only kept alive by cycles
+fn assert_only_cycles_leak(source: &str) {
+    let before = live_objects();
+    let mut vm = Vm::default();
+    vm.eval(source).unwrap();
+    assert!(vm.stack().is_empty());
+    assert!(live_objects() > before, "The program didn't make a cycle");
+    assert!(collect_cycles() > 0);
+    assert_eq!(live_objects(), before);
+}
+
+#[test]
+fn closure_stored_in_its_own_captured_local_is_collected() {
+    assert_only_cycles_leak(
+        "
+        proc() -> () { } var(:f)
+        proc() -> () { get(:f) load drop } get(:f) store
+        ",
+    );
+}
+
+#[test]
+fn coroutine_stored_in_its_own_captured_local_is_collected() {
+    assert_only_cycles_leak(
+        "
+        proc() -> () { } coroutine var(:c)
+        proc() -> () { get(:c) load drop } coroutine get(:c) store
+        ",
+    );
+}
+
+#[test]
+fn cycle_reachable_from_the_stack_is_kept() {
+    let mut vm = Vm::default();
+    vm.eval("proc() -> () { } var(:f) proc() -> () { get(:f) load drop } get(:f) store get(:f)")
+        .unwrap();
+    assert_eq!(collect_cycles(), 0);
+    vm.eval("load call").unwrap();
+    assert!(collect_cycles() > 0);
+}
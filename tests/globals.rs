@@ -0,0 +1,71 @@
+use std::rc::Rc;
+
+use stack_lang::*;
+
+fn counter_vm() -> Vm {
+    Vm::builder().global("count", Value::Integer(0)).build()
+}
+
+#[test]
+fn programs_share_globals_with_the_host() {
+    let mut vm = counter_vm();
+    vm.eval("get(:count) load 1 add get(:count) store").unwrap();
+    vm.eval("get(:count) load 1 add get(:count) store").unwrap();
+    assert_eq!(vm.global_as::<i64>("count"), Some(2));
+    vm.set_global("count", Value::Integer(10)).unwrap();
+    vm.eval("get(:count) load").unwrap();
+    assert_eq!(vm.stack(), [Value::Integer(10)]);
+}
+
+#[test]
+fn const_procedures_share_globals_too() {
+    let mut vm = counter_vm();
+    vm.eval(
+        "const(:inc proc() -> () { get(:count) load 1 add get(:count) store })
+        inc call inc call",
+    )
+    .unwrap();
+    assert_eq!(vm.global_as::<i64>("count"), Some(2));
+}
+
+#[test]
+fn declared_global_cell_is_shared() {
+    let mut vm = Vm::default();
+    let cell = vm.declare_global("name", Value::String("a".into()));
+    vm.eval(r#"get(:name) load "b" concat get(:name) store"#)
+        .unwrap();
+    assert_eq!(cell.replace(Value::Null), Value::String("ab".into()));
+    cell.set(Value::String("c".into()));
+    assert_eq!(vm.global("name"), Some(Value::String("c".into())));
+    assert!(Rc::ptr_eq(&vm.global_cell("name").unwrap(), &cell));
+}
+
+#[test]
+fn global_of_another_rust_type_is_nothing() {
+    let vm = counter_vm();
+    assert_eq!(vm.global_as::<String>("count"), None);
+    assert_eq!(vm.global_as::<i64>("missing"), None);
+}
+
+#[test]
+fn set_global_keeps_the_declared_type() {
+    let vm = counter_vm();
+    match vm.set_global("count", Value::Boolean(true)) {
+        Err(GlobalError::WrongType { expected, found }) => {
+            assert_eq!(expected, Type::Integer);
+            assert_eq!(found, Type::Boolean);
+        }
+        result => panic!("Expected a type error but got {result:?}"),
+    }
+    assert!(matches!(
+        vm.set_global("missing", Value::Integer(1)),
+        Err(GlobalError::Undeclared(_))
+    ));
+    assert_eq!(vm.global_as::<i64>("count"), Some(0));
+}
+
+#[test]
+#[should_panic(expected = "Redeclaration of global 'count'")]
+fn global_cannot_be_declared_twice() {
+    counter_vm().declare_global("count", Value::Integer(1));
+}
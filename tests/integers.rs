@@ -0,0 +1,105 @@
+use stack_lang::*;
+
+fn run(source: &str) -> Vec<Value> {
+    let mut vm = Vm::default();
+    vm.eval(source).unwrap();
+    vm.stack().to_vec()
+}
+
+#[test]
+fn arithmetic_wraps_around() {
+    let stack = run("
+        200u8 100u8 add
+        16u8 17u8 mul
+        2147483647i32 1i32 add
+        65536i32 65536i32 mul
+        18446744073709551615u64 2u64 add
+        9223372036854775808u64 2u64 mul
+        0u8 1u8 sub
+    ");
+    assert_eq!(
+        stack,
+        [
+            Value::U8(44),
+            Value::U8(16),
+            Value::I32(i32::MIN),
+            Value::I32(0),
+            Value::U64(1),
+            Value::U64(0),
+            Value::U8(255),
+        ]
+    );
+}
+
+#[test]
+fn shifting_by_the_width_or_more_shifts_every_bit_out() {
+    let stack = run("
+        1 64 shl
+        1u8 8 shl
+        255u8 200 shr
+        1u64 64 shl
+        18446744073709551615u64 64 shr
+        1i32 32 shl
+        9223372036854775807 100 shr
+    ");
+    assert_eq!(
+        stack,
+        [
+            Value::Integer(0),
+            Value::U8(0),
+            Value::U8(0),
+            Value::U64(0),
+            Value::U64(0),
+            Value::I32(0),
+            Value::Integer(0),
+        ]
+    );
+}
+
+#[test]
+fn shifting_negative_values_right_keeps_the_sign() {
+    let stack = run("
+        0 8 sub 1 shr
+        0 8 sub 64 shr
+        0 8 sub to_i32 2 shr
+        0 8 sub to_i32 40 shr
+        0 8 sub to_u64 60 shr
+    ");
+    assert_eq!(
+        stack,
+        [
+            Value::Integer(-4),
+            Value::Integer(-1),
+            Value::I32(-2),
+            Value::I32(-1),
+            Value::U64(15),
+        ]
+    );
+}
+
+#[test]
+fn conversions_truncate_to_the_target_width() {
+    let stack = run("
+        300 to_u8
+        0 1 sub to_u8
+        4294967297 to_i32
+        2147483648 to_i32
+        0 1 sub to_u64
+        18446744073709551615u64 to_int
+        18446744073709551615u64 to_i32
+        200u8 to_i32
+    ");
+    assert_eq!(
+        stack,
+        [
+            Value::U8(44),
+            Value::U8(255),
+            Value::I32(1),
+            Value::I32(i32::MIN),
+            Value::U64(u64::MAX),
+            Value::Integer(-1),
+            Value::I32(-1),
+            Value::I32(200),
+        ]
+    );
+}
@@ -0,0 +1,81 @@
+use stack_lang::*;
+
+fn run_with(limits: Limits, source: &str) -> Result<(), RuntimeError> {
+    Vm::builder().stdlib().limits(limits).build().eval(source)
+}
+
+#[test]
+fn deep_recursion_exhausts_the_call_stack() {
+    let limits = Limits {
+        max_call_depth: 10,
+        ..Default::default()
+    };
+    let result = run_with(
+        limits,
+        "
+        proc() -> () { } var(:forever)
+        proc() -> () { get(:forever) load call 0 drop } get(:forever) store
+        get(:forever) load call
+        ",
+    );
+    assert!(matches!(result, Err(RuntimeError::CallStackExhausted)));
+}
+
+#[test]
+fn endless_loop_runs_out_of_fuel() {
+    let limits = Limits {
+        fuel: 1000,
+        ..Default::default()
+    };
+    let result = run_with(limits, "while true { }");
+    assert!(matches!(result, Err(RuntimeError::OutOfFuel)));
+}
+
+#[test]
+fn fuel_left_is_kept_between_programs() {
+    let mut vm = Vm::builder()
+        .limits(Limits {
+            fuel: 1000,
+            ..Default::default()
+        })
+        .build();
+    vm.eval("1 2 add drop").unwrap();
+    let used = 1000 - vm.limits().fuel;
+    assert!(used > 0);
+    vm.eval("1 2 add drop").unwrap();
+    assert_eq!(vm.limits().fuel, 1000 - 2 * used);
+}
+
+#[test]
+fn too_many_values_exhaust_the_stack() {
+    let limits = Limits {
+        max_stack_depth: 3,
+        ..Default::default()
+    };
+    assert!(run_with(limits.clone(), "1 2 3").is_ok());
+    let result = run_with(limits, "1 2 3 4");
+    assert!(matches!(result, Err(RuntimeError::ValueStackExhausted)));
+}
+
+#[test]
+fn concat_cannot_grow_a_string_past_the_limit() {
+    let limits = Limits {
+        max_value_length: 4,
+        ..Default::default()
+    };
+    assert!(run_with(limits.clone(), r#""ab" "cd" concat"#).is_ok());
+    let result = run_with(limits, r#""ab" "cde" concat"#);
+    assert!(matches!(result, Err(RuntimeError::ValueTooLong)));
+}
+
+#[test]
+fn builtin_results_are_held_to_the_length_limit() {
+    let limits = Limits {
+        max_value_length: 10,
+        ..Default::default()
+    };
+    let result = run_with(limits.clone(), r#""aaaa" "a" "aaaa" string_replace call"#);
+    assert!(matches!(result, Err(RuntimeError::ValueTooLong)));
+    let result = run_with(limits, r#""a,b,c,d,e,f,g,h,i,j,k" "," string_split call"#);
+    assert!(matches!(result, Err(RuntimeError::ValueTooLong)));
+}
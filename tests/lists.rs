@@ -0,0 +1,72 @@
+use stack_lang::*;
+
+fn run(source: &str) -> Vec<Value> {
+    let mut vm = Vm::builder().stdlib().build();
+    vm.eval(source).unwrap();
+    vm.stack().to_vec()
+}
+
+fn ints(values: &[i64]) -> Value {
+    Value::List(
+        Type::Integer,
+        values.iter().copied().map(Value::Integer).collect(),
+    )
+}
+
+#[test]
+fn list_is_built_from_an_empty_one() {
+    let stack = run("empty_list(int) 1 list_push 2 list_push 3 list_push");
+    assert_eq!(stack, [ints(&[1, 2, 3])]);
+}
+
+#[test]
+fn empty_list_keeps_its_element_type() {
+    let stack = run("empty_list(string list) dup list_length");
+    assert_eq!(
+        stack,
+        [
+            Value::List(Type::List(Box::new(Type::String)), vec![]),
+            Value::Integer(0),
+        ]
+    );
+}
+
+#[test]
+fn list_length_and_get() {
+    let stack = run("empty_list(int) 5 list_push 7 list_push dup list_length swap 1 list_get");
+    assert_eq!(stack, [Value::Integer(2), Value::Integer(7)]);
+}
+
+#[test]
+fn list_get_out_of_range_throws() {
+    let stack = run(r#"
+        empty_list(int) 5 list_push var(:list)
+        try { get(:list) load 1 list_get } catch(string) { drop 0 1 sub }
+        try { get(:list) load 0 1 sub list_get } catch(string) { drop 0 1 sub }
+    "#);
+    assert_eq!(stack, [Value::Integer(-1), Value::Integer(-1)]);
+}
+
+#[test]
+fn for_each_goes_through_a_list() {
+    let stack = run("
+        0 empty_list(int) 1 list_push 2 list_push 3 list_push
+        for_each(:n) { get(:n) load add }
+    ");
+    assert_eq!(stack, [Value::Integer(6)]);
+}
+
+#[test]
+fn list_type_can_be_written_in_procedure_types() {
+    let stack = run(r#"
+        proc(string list) -> (string) { "+" string_join call }
+        empty_list(string) "a" list_push "b" list_push swap call
+    "#);
+    assert_eq!(stack, [Value::String("a+b".into())]);
+}
+
+#[test]
+#[should_panic(expected = "Expected a value of type 'int' to push but got 'string'")]
+fn list_push_checks_the_element_type() {
+    run(r#"empty_list(int) "a" list_push"#);
+}
@@ -0,0 +1,54 @@
+use stack_lang::*;
+
+fn run(source: &str) -> Vec<Value> {
+    let mut vm = Vm::default();
+    vm.eval(source).unwrap();
+    vm.stack().to_vec()
+}
+
+#[test]
+fn negative_step_counts_down_to_the_end() {
+    let stack = run("
+        0 5 0 0 1 sub for_step(:i) { 10 mul get(:i) load add }
+        0 0 5 0 1 sub for_step(:i) { get(:i) load add }
+    ");
+    assert_eq!(stack, [Value::Integer(54321), Value::Integer(0)]);
+}
+
+#[test]
+#[should_panic(expected = "The step of a for loop cannot be 0")]
+fn step_of_zero() {
+    run("0 10 0 for_step(:i) { }");
+}
+
+#[test]
+#[should_panic(expected = "The step of a for loop cannot be 0")]
+fn step_computed_to_be_zero() {
+    run("0 10 3 3 sub for_step(:i) { }");
+}
+
+#[test]
+#[should_panic(expected = "Expected the for loop step to be an int but got 'u8'")]
+fn step_that_is_not_an_int() {
+    run("0 10 1u8 for_step(:i) { }");
+}
+
+#[test]
+#[should_panic(expected = "Expected the for loop step on the stack but got nothing")]
+fn step_missing() {
+    run("for_step(:i) { }");
+}
+
+#[test]
+#[should_panic(
+    expected = "The number of elements after the for body must be the same as before the for"
+)]
+fn body_that_changes_the_stack() {
+    run("10 0 0 1 sub for_step(:i) { get(:i) load }");
+}
+
+#[test]
+#[should_panic(expected = "Unable to find name 'i'")]
+fn loop_variable_used_after_the_loop() {
+    run("10 0 0 1 sub for_step(:i) { } get(:i)");
+}
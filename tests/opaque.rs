@@ -0,0 +1,66 @@
+use std::rc::Rc;
+
+use stack_lang::*;
+
+struct File {
+    name: String,
+}
+
+impl OpaqueType for File {
+    const NAME: &'static str = "file";
+}
+
+/// Another host type that happens to have the same name
+struct Impostor;
+
+impl OpaqueType for Impostor {
+    const NAME: &'static str = "file";
+}
+
+fn vm() -> Vm {
+    Vm::builder()
+        .opaque_type::<File>()
+        .register("file_name", |file: Rc<File>| file.name.clone())
+        .register("open", |name: String| Rc::new(File { name }))
+        .build()
+}
+
+#[test]
+fn host_value_can_be_passed_to_and_from_programs() {
+    let mut vm = vm();
+    vm.push(Value::opaque(File {
+        name: "a.txt".into(),
+    }));
+    vm.eval(r#"dup file_name call swap drop "b.txt" open call"#)
+        .unwrap();
+    let file = vm.pop().unwrap();
+    assert_eq!(file.get_type(), Type::Opaque("file".into()));
+    assert_eq!(file.downcast_ref::<File>().unwrap().name, "b.txt");
+    assert!(file.downcast_ref::<Impostor>().is_none());
+    assert_eq!(vm.pop(), Some(Value::String("a.txt".into())));
+}
+
+#[test]
+fn opaque_type_can_be_named_in_programs() {
+    let mut vm = vm();
+    vm.eval(r#"proc(file) -> (string) { file_name call } "c.txt" open call swap call"#)
+        .unwrap();
+    assert_eq!(vm.stack(), [Value::String("c.txt".into())]);
+}
+
+#[test]
+fn host_value_of_another_rust_type_is_thrown_rather_than_a_panic() {
+    let mut vm = vm();
+    vm.push(Value::opaque(Impostor));
+    let result = vm.eval("file_name call");
+    assert!(matches!(
+        result,
+        Err(RuntimeError::Thrown(Value::String(_)))
+    ));
+
+    vm.clear_stack();
+    vm.push(Value::opaque(Impostor));
+    vm.eval(r#"var(:f) try { get(:f) load file_name call } catch(string) { drop "caught" }"#)
+        .unwrap();
+    assert_eq!(vm.stack(), [Value::String("caught".into())]);
+}
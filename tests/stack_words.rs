@@ -0,0 +1,66 @@
+use stack_lang::*;
+
+fn run(source: &str) -> Vec<Value> {
+    let mut vm = Vm::default();
+    vm.eval(source).unwrap();
+    vm.stack().to_vec()
+}
+
+#[test]
+fn pick_roll_and_dip_reach_under_the_top() {
+    let stack = run("
+        1 2 3 pick(2) roll(3)
+        proc(int) -> (int) { 10 mul } dip
+    ");
+    assert_eq!(
+        stack,
+        [
+            Value::Integer(2),
+            Value::Integer(3),
+            Value::Integer(10),
+            Value::Integer(1),
+        ]
+    );
+}
+
+#[test]
+#[should_panic(expected = "Expected at least 3 elements to pick but the stack only has 2 elements")]
+fn pick_deeper_than_the_stack() {
+    run("1 2 pick(2)");
+}
+
+#[test]
+#[should_panic(expected = "Expected at least 2 elements to roll but the stack only has 1 elements")]
+fn roll_deeper_than_the_stack() {
+    run("1 roll(1)");
+}
+
+#[test]
+#[should_panic(expected = "pick depth must be positive but got -1")]
+fn pick_with_a_negative_depth() {
+    run("1 2 pick(0 1 sub)");
+}
+
+#[test]
+#[should_panic(expected = "Expected exactly one integer as the roll depth")]
+fn roll_with_a_depth_that_is_not_an_integer() {
+    run(r#"1 2 roll("one")"#);
+}
+
+#[test]
+#[should_panic(expected = "Expected a value to dip under on the stack but got nothing")]
+fn dip_with_nothing_to_dip_under() {
+    run("proc() -> () { } dip");
+}
+
+#[test]
+#[should_panic(expected = "Expected a procedure to call but got type 'int'")]
+fn dip_with_a_value_that_is_not_a_procedure() {
+    run("1 2 dip");
+}
+
+#[test]
+#[should_panic(expected = "Expected argument 0 on the stack but got nothing")]
+fn dip_with_the_procedure_missing_its_argument() {
+    run("1 proc(int) -> (int) { } dip");
+}
@@ -0,0 +1,139 @@
+use stack_lang::*;
+
+fn run(source: &str) -> Vec<Value> {
+    let mut vm = Vm::builder().stdlib().build();
+    vm.eval(source).unwrap();
+    vm.stack().to_vec()
+}
+
+fn string(value: &str) -> Value {
+    Value::String(value.into())
+}
+
+/// Runs a program that is expected to throw a string, returning it
+fn thrown(source: &str) -> String {
+    let mut vm = Vm::builder().stdlib().build();
+    match vm.eval(source) {
+        Err(RuntimeError::Thrown(Value::String(message))) => message,
+        result => panic!("Expected a string to be thrown but got {result:?}"),
+    }
+}
+
+#[test]
+fn indices_count_chars() {
+    let stack = run(r#"
+        "héllo" string_length call
+        "héllo" 1 string_get call
+        "héllo" 1 3 substring call
+    "#);
+    assert_eq!(
+        stack,
+        [Value::Integer(5), Value::Character('é'), string("él")]
+    );
+}
+
+#[test]
+fn indices_out_of_range_throw() {
+    assert_eq!(
+        thrown(r#""abc" 3 string_get call"#),
+        "Index 3 is out of range for a string of length 3"
+    );
+    assert_eq!(
+        thrown(r#""abc" 2 1 substring call"#),
+        "Cannot take 2..1 out of a string of length 3"
+    );
+}
+
+#[test]
+fn string_find_pushes_whether_it_was_found() {
+    let stack = run(r#"
+        "añb-c" "-" string_find call
+        "abc" "x" string_find call
+    "#);
+    assert_eq!(
+        stack,
+        [
+            Value::Integer(3),
+            Value::Boolean(true),
+            Value::Integer(0),
+            Value::Boolean(false),
+        ]
+    );
+}
+
+#[test]
+fn split_and_join() {
+    let stack = run(r#"
+        "a,b,,c" "," string_split call dup
+        " " string_join call
+    "#);
+    assert_eq!(
+        stack,
+        [
+            Value::List(
+                Type::String,
+                vec![string("a"), string("b"), string(""), string("c")]
+            ),
+            string("a b  c"),
+        ]
+    );
+    assert_eq!(
+        thrown(r#""abc" "" string_split call"#),
+        "Cannot split on an empty separator"
+    );
+}
+
+#[test]
+fn replace_trim_and_case() {
+    let stack = run(r#"
+        "a-b-c" "-" "+" string_replace call
+        "  padded " string_trim call
+        "MiXeD" string_upper call
+        "MiXeD" string_lower call
+    "#);
+    assert_eq!(
+        stack,
+        [
+            string("a+b+c"),
+            string("padded"),
+            string("MIXED"),
+            string("mixed")
+        ]
+    );
+}
+
+#[test]
+fn starts_and_ends_with() {
+    let stack = run(r#"
+        "prefix" "pre" string_starts_with call
+        "prefix" "fix" string_starts_with call
+        "prefix" "fix" string_ends_with call
+    "#);
+    assert_eq!(
+        stack,
+        [
+            Value::Boolean(true),
+            Value::Boolean(false),
+            Value::Boolean(true)
+        ]
+    );
+}
+
+#[test]
+fn ints_to_and_from_strings() {
+    let stack = run(r#"
+        0 42 sub int_to_string call
+        " 17 " string_to_int call
+    "#);
+    assert_eq!(stack, [string("-42"), Value::Integer(17)]);
+    assert!(thrown(r#""12a" string_to_int call"#).starts_with("Cannot convert \"12a\" to an int"));
+}
+
+#[test]
+fn bytes_convert_to_a_string_only_when_valid_utf8() {
+    assert_eq!(run(r#""héllo" to_bytes to_string"#), [string("héllo")]);
+    assert_eq!(
+        thrown(r#""a" to_bytes 255u8 bytes_push to_string"#),
+        "Cannot convert bytes to a string: invalid utf-8 sequence of 1 bytes from index 1"
+    );
+}
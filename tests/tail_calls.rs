@@ -0,0 +1,41 @@
+use std::{collections::HashMap, rc::Rc};
+
+use stack_lang::*;
+
+#[test]
+fn tail_recursive_countdown_runs_in_constant_memory() {
+    let source = "
+        proc(int) -> (int) { } var(:countdown)
+        proc(int) -> (int) {
+            dup 0 equal if { return }
+            1 sub get(:countdown) load call
+        } get(:countdown) store
+        10000000 get(:countdown) load call
+    ";
+    let ops = compile_ops(source, &HashMap::new(), HashMap::new());
+    let chunk = Rc::new(lower(&ops, &[]));
+    let mut stack = vec![];
+    // without tail calls the countdown would need ten million frames
+    let mut limits = Limits {
+        max_call_depth: 2,
+        ..Default::default()
+    };
+    execute_with_limits(&chunk, &mut stack, HashMap::new(), &mut limits).unwrap();
+    assert_eq!(stack, [Value::Integer(0)]);
+}
+
+#[test]
+fn call_inside_a_try_is_not_a_tail_call() {
+    // replacing the frame would drop the handler, letting the throw escape the `try`
+    let source = r#"
+        proc() -> (int) { "boom" throw } var(:boom)
+        proc() -> (int) {
+            try { get(:boom) load call return } catch(string) { drop 7 }
+        } call
+    "#;
+    let ops = compile_ops(source, &HashMap::new(), HashMap::new());
+    let chunk = Rc::new(lower(&ops, &[]));
+    let mut stack = vec![];
+    execute(&chunk, &mut stack, HashMap::new()).unwrap();
+    assert_eq!(stack, [Value::Integer(7)]);
+}
@@ -0,0 +1,56 @@
+use stack_lang::*;
+
+#[test]
+fn stack_is_kept_between_programs() {
+    let mut vm = Vm::default();
+    vm.eval("1 2").unwrap();
+    vm.eval("add").unwrap();
+    assert_eq!(vm.pop(), Some(Value::Integer(3)));
+    assert!(vm.stack().is_empty());
+}
+
+#[test]
+fn missing_file_is_an_error() {
+    let mut vm = Vm::default();
+    let result = vm.run_file("does/not/exist.stack");
+    assert!(matches!(result, Err(RunFileError::Read(_))));
+}
+
+#[test]
+fn fuel_can_be_topped_up_after_running_out() {
+    let mut vm = Vm::builder()
+        .limits(Limits {
+            fuel: 100,
+            ..Default::default()
+        })
+        .build();
+    let countdown = "50 while dup 0 greater { 1 sub } drop";
+    assert!(matches!(vm.eval(countdown), Err(RuntimeError::OutOfFuel)));
+    vm.clear_stack();
+    assert!(matches!(vm.eval("1 drop"), Err(RuntimeError::OutOfFuel)));
+    vm.limits_mut().fuel = 10_000;
+    vm.eval(countdown).unwrap();
+    assert!(vm.limits().fuel < 10_000);
+}
+
+#[test]
+fn interrupt_stops_programs_until_it_is_reset() {
+    let mut vm = Vm::default();
+    let interrupt = vm.interrupt_handle();
+    interrupt.interrupt();
+    assert!(matches!(vm.eval("1"), Err(RuntimeError::Interrupted)));
+    interrupt.reset();
+    vm.eval("1").unwrap();
+    assert_eq!(vm.stack(), [Value::Integer(1)]);
+}
+
+#[test]
+fn program_can_be_run_a_few_steps_at_a_time() {
+    let mut vm = Vm::default();
+    vm.eval("10").unwrap();
+    let mut execution = vm.start("0 swap while dup 0 greater { swap 1 add swap 1 sub } drop");
+    assert!(vm.stack().is_empty());
+    while execution.run_steps(3).unwrap() == Progress::Paused {}
+    vm.finish(execution);
+    assert_eq!(vm.stack(), [Value::Integer(10)]);
+}